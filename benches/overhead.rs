@@ -37,7 +37,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         unsafe {
             AllocationRegistry::clear_global_tracker();
         }
-        let _ = AllocationRegistry::set_global_tracker(NoopTracker)
+        AllocationRegistry::set_global_tracker(NoopTracker)
             .expect("no other global tracker should be set");
 
         b.iter(|| Vec::<String>::with_capacity(128));
@@ -49,7 +49,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         unsafe {
             AllocationRegistry::clear_global_tracker();
         }
-        let _ = AllocationRegistry::set_global_tracker(NoopTracker)
+        AllocationRegistry::set_global_tracker(NoopTracker)
             .expect("no other global tracker should be set");
         AllocationRegistry::enable_tracking();
 
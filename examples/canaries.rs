@@ -0,0 +1,83 @@
+use tracking_allocator::{
+    AllocationGroupId, AllocationGroupToken, AllocationRegistry, AllocationTracker, Allocator,
+};
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Here, we're creating our allocator with buffer overflow detection enabled.  Every allocation gets
+// padded with canary bytes on either side, and those canaries are checked when the allocation is
+// deallocated.  This costs some extra memory per allocation, so it's something you'd typically only
+// turn on when hunting down memory corruption.
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::with_canaries(System);
+
+// Just like in the `stdout` example, we can't allocate in our tracker, so we simply record the
+// details of the most recent corruption in some atomics that we can read once we're done.
+static CORRUPTED_ADDR: AtomicUsize = AtomicUsize::new(0);
+static CORRUPTED_SIZE: AtomicUsize = AtomicUsize::new(0);
+static CORRUPTED_GROUP: AtomicUsize = AtomicUsize::new(0);
+
+struct CorruptionTracker {
+    group_id: AllocationGroupId,
+}
+
+impl AllocationTracker for CorruptionTracker {
+    fn allocated(&self, _addr: usize, _size: usize, _group_id: AllocationGroupId) {}
+
+    fn deallocated(&self, _addr: usize, _current_group_id: AllocationGroupId) {}
+
+    // If we didn't override this method, the default behavior would be to print the details of the
+    // corruption to stderr and abort the process, which is typically what you want: once memory has
+    // been corrupted, all bets are off.  For the purpose of the example, though, we'll just record
+    // it and keep going.
+    //
+    // The group ID here is the group that was active when the allocation was _made_, which is the
+    // information you need to track down the culprit.
+    fn corrupted(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        CORRUPTED_ADDR.store(addr, Ordering::SeqCst);
+        CORRUPTED_SIZE.store(size, Ordering::SeqCst);
+        CORRUPTED_GROUP.store((group_id == self.group_id) as usize, Ordering::SeqCst);
+    }
+}
+
+fn main() {
+    let token = AllocationGroupToken::register().expect("failed to register allocation group");
+
+    AllocationRegistry::set_global_tracker(CorruptionTracker {
+        group_id: token.id(),
+    })
+    .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    let guard = token.enter();
+
+    // Well-behaved allocations never trip the canaries.
+    let v = vec![1u8; 32];
+    drop(v);
+
+    // Now we'll misbehave, and write one byte past the end of an allocation.  This is, of course,
+    // undefined behavior, and exactly the kind of bug canaries are meant to catch.
+    let layout = Layout::from_size_align(32, 8).expect("layout should be valid");
+    let addr = unsafe {
+        let ptr = GLOBAL.alloc(layout);
+        ptr.add(32).write(0x42);
+        GLOBAL.dealloc(ptr, layout);
+        ptr as usize
+    };
+
+    drop(guard);
+    AllocationRegistry::disable_tracking();
+
+    // We should see the address of our misbehaving allocation, along with its size, and that it
+    // was made within our allocation group.
+    println!(
+        "corruption -> addr={:#x} size={} in_local_group={} (expected addr={:#x})",
+        CORRUPTED_ADDR.load(Ordering::SeqCst),
+        CORRUPTED_SIZE.load(Ordering::SeqCst),
+        CORRUPTED_GROUP.load(Ordering::SeqCst) == 1,
+        addr
+    );
+}
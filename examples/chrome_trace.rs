@@ -0,0 +1,43 @@
+use tracking_allocator::{
+    AllocationGroupToken, AllocationRegistry, Allocator, ChromeTraceSampler, StatsTracker,
+};
+
+use std::{alloc::System, fs::File, sync::Arc, thread, time::Duration};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // `StatsTracker` aggregates allocation statistics for each allocation group.  We install it as
+    // the global tracker, and keep a reference around so that we can read from it.
+    let stats = Arc::new(StatsTracker::new());
+    AllocationRegistry::set_global_tracker(Arc::clone(&stats))
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    // The sampler reads the live bytes of every allocation group from the tracker on a background
+    // thread, and writes them out as counter events.
+    let file = File::create("chrome-trace.json").expect("failed to create chrome-trace.json");
+    let sampler = ChromeTraceSampler::new(Arc::clone(&stats))
+        .with_interval(Duration::from_millis(10))
+        .spawn(file)
+        .expect("failed to spawn sampler");
+
+    // Grow a buffer within an allocation group for a little while, and then drop it, so that the
+    // counter track for our group ramps up and then falls back to zero.
+    let token = AllocationGroupToken::register().expect("failed to register allocation group");
+    let guard = token.enter();
+    let mut buffers = Vec::new();
+    for _ in 0..20 {
+        buffers.push(vec![0u8; 64 * 1024]);
+        thread::sleep(Duration::from_millis(5));
+    }
+    drop(buffers);
+    drop(guard);
+    thread::sleep(Duration::from_millis(20));
+
+    // Once finished, the trace can be loaded in `chrome://tracing` or https://ui.perfetto.dev.
+    sampler.finish().expect("failed to write chrome-trace.json");
+    AllocationRegistry::disable_tracking();
+    println!("wrote chrome-trace.json");
+}
@@ -0,0 +1,44 @@
+use tracking_allocator::{AllocationGroupToken, AllocationRegistry, Allocator, DhatTracker};
+
+use std::{alloc::System, fs::File, sync::Arc};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // `DhatTracker` is a built-in tracker that collects everything DHAT needs to build a heap
+    // profile.  We hold on to our own reference to the tracker so that we can write out the profile
+    // at the end: `Arc<T>` is itself a tracker whenever `T` is.
+    let tracker = Arc::new(DhatTracker::new());
+    AllocationRegistry::set_global_tracker(Arc::clone(&tracker))
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    // DHAT normally groups allocations by the stack trace they were made from, but here, each
+    // allocation group shows up as its own "program point" instead.
+    let parsing = AllocationGroupToken::register().expect("failed to register allocation group");
+    let caching = AllocationGroupToken::register().expect("failed to register allocation group");
+
+    // Some short-lived allocations...
+    let guard = parsing.enter();
+    for i in 0..100 {
+        let s = format!("line {}", i);
+        drop(s);
+    }
+    drop(guard);
+
+    // ...and some that are still alive when we write out the profile.
+    let guard = caching.enter();
+    let cache = (0..100).map(|i| vec![i as u8; 64]).collect::<Vec<_>>();
+    drop(guard);
+
+    AllocationRegistry::disable_tracking();
+
+    // The resulting file can be loaded in DHAT's viewer, found at
+    // https://nnethercote.github.io/dh_view/dh_view.html.
+    let file = File::create("dhat-heap.json").expect("failed to create dhat-heap.json");
+    tracker
+        .write_json(file)
+        .expect("failed to write dhat-heap.json");
+    println!("wrote dhat-heap.json ({} cached entries)", cache.len());
+}
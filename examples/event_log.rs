@@ -0,0 +1,61 @@
+use tracking_allocator::{
+    AllocationEvent, AllocationGroupToken, AllocationRegistry, Allocator, EventLogReader,
+    EventLogTracker,
+};
+
+use std::{alloc::System, fs::File, io::BufWriter, thread};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // `EventLogTracker` records every allocation event to a compact binary log, doing the actual
+    // writing on a background thread.  We get back a handle to that background thread, which we use
+    // to make sure everything has been written out once we're done.
+    let file = File::create("allocations.talog").expect("failed to create allocations.talog");
+    let (tracker, handle) =
+        EventLogTracker::new(BufWriter::new(file)).expect("failed to create event log tracker");
+    AllocationRegistry::set_global_tracker(tracker)
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    // Do some allocating from a few threads, within an allocation group.
+    let workers = (0..4)
+        .map(|i| {
+            thread::spawn(move || {
+                let token =
+                    AllocationGroupToken::register().expect("failed to register allocation group");
+                let _guard = token.enter();
+                let data = (0..100).map(|j| vec![i as u8; j]).collect::<Vec<_>>();
+                data.len()
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().expect("worker panicked");
+    }
+
+    AllocationRegistry::disable_tracking();
+    handle.finish().expect("failed to write allocations.talog");
+
+    // Now we can read the log back, which is typically something you'd do in another process
+    // entirely, so that the analysis doesn't perturb what's being measured.
+    let file = File::open("allocations.talog").expect("failed to open allocations.talog");
+    let reader = EventLogReader::new(file).expect("failed to read event log header");
+
+    let (mut allocations, mut deallocations, mut bytes) = (0, 0, 0);
+    for event in reader {
+        match event.expect("failed to read event") {
+            AllocationEvent::Allocated { size, .. } => {
+                allocations += 1;
+                bytes += size;
+            }
+            AllocationEvent::Deallocated { .. } => deallocations += 1,
+        }
+    }
+
+    println!(
+        "read {} allocations ({} bytes) and {} deallocations from allocations.talog",
+        allocations, bytes, deallocations
+    );
+}
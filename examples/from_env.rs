@@ -0,0 +1,23 @@
+use tracking_allocator::{AllocationGroupToken, AllocationRegistry, Allocator};
+
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // Nothing is tracked unless `TRACKING_ALLOCATOR_ENABLE=1` is set, in which case the tracker is
+    // chosen, and configured, by the other `TRACKING_ALLOCATOR_*` variables.  Try running this with
+    // `TRACKING_ALLOCATOR_ENABLE=1 TRACKING_ALLOCATOR_FORMAT=prometheus`, for example.
+    //
+    // The handle writes out the output once it's dropped at the end of `main`, so we need to keep
+    // it around until then.
+    let _profiler = AllocationRegistry::configure_from_env().expect("failed to configure tracking");
+
+    let token = AllocationGroupToken::register_with_tags([("component", "example")])
+        .expect("failed to register allocation group");
+    let _guard = token.enter();
+
+    let data = (0..100).map(|i| vec![0u8; i * 8]).collect::<Vec<_>>();
+    println!("allocated {} buffers", data.len());
+}
@@ -0,0 +1,63 @@
+use tracking_allocator::{
+    AllocationGroupExt, AllocationGroupId, AllocationGroupToken, AllocationRegistry,
+    AllocationTracker, Allocator,
+};
+
+use std::{
+    alloc::System,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+static GROUP_ONE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingTracker;
+
+impl AllocationTracker for CountingTracker {
+    fn allocated(&self, _addr: usize, _size: usize, group_id: AllocationGroupId) {
+        if group_id.as_usize() == 1 {
+            GROUP_ONE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn deallocated(&self, _addr: usize, _current_group_id: AllocationGroupId) {}
+}
+
+async fn work() -> usize {
+    let mut data = Vec::new();
+    for i in 0..10 {
+        data.push(vec![0u8; i * 64]);
+
+        // Yielding lets the runtime run other tasks, and possibly move us to another thread, which
+        // is exactly what an `AllocationGuard` couldn't cope with.
+        tokio::task::yield_now().await;
+    }
+    data.len()
+}
+
+fn main() {
+    AllocationRegistry::set_global_tracker(CountingTracker)
+        .expect("no other global tracker should be set yet");
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("failed to build runtime");
+
+    AllocationRegistry::enable_tracking();
+
+    // Wrapping the future means the allocation group is entered every time the future is polled,
+    // and exited again when it yields, so only the future's own allocations are attributed to it.
+    let token = AllocationGroupToken::register().expect("failed to register allocation group");
+    let handle = runtime.spawn(work().with_allocation_group(token));
+    let buffers = runtime.block_on(handle).expect("task panicked");
+
+    AllocationRegistry::disable_tracking();
+
+    println!(
+        "made {} buffers with {} allocations in allocation group 1",
+        buffers,
+        GROUP_ONE_ALLOCATIONS.load(Ordering::Relaxed)
+    );
+}
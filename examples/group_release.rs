@@ -0,0 +1,55 @@
+use tracking_allocator::{AllocationGroupToken, AllocationRegistry, Allocator, StatsTracker};
+
+use std::{alloc::System, sync::Arc};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    let stats = Arc::new(StatsTracker::new());
+    AllocationRegistry::set_global_tracker(Arc::clone(&stats))
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    // Simulate a long-running service that gives each request its own allocation group.  Each
+    // group is released as soon as its token is dropped, so its index is reused by the next one,
+    // and `StatsTracker` folds its statistics into those of the retired groups.
+    let mut leaked = Vec::new();
+    for request in 0..1000 {
+        let token = AllocationGroupToken::register().expect("failed to register allocation group");
+        if request % 250 == 0 {
+            let id = token.id();
+            println!(
+                "request {:>3} ran in group {} (generation {})",
+                request,
+                id.as_usize(),
+                id.generation()
+            );
+        }
+
+        token.in_scope(|| {
+            let _scratch = vec![0u8; 1024];
+
+            // Every hundredth request leaks a little memory, which outlives its group.
+            if request % 100 == 0 {
+                leaked.push(vec![0u8; 64]);
+            }
+        });
+    }
+
+    AllocationRegistry::disable_tracking();
+
+    let retired = stats.retired();
+    println!(
+        "{} retired groups allocated {} bytes, of which {} bytes are still live",
+        retired.groups,
+        retired.allocated_bytes,
+        retired.live_bytes()
+    );
+    println!(
+        "{} groups still have statistics of their own",
+        stats.snapshot().len()
+    );
+
+    drop(leaked);
+}
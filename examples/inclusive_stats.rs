@@ -0,0 +1,50 @@
+use tracking_allocator::{AllocationGroupToken, AllocationRegistry, Allocator, StatsTracker};
+
+use std::{alloc::System, sync::Arc};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // A tracker created with `with_inclusive_stats` credits each allocation to every allocation
+    // group active at the time, and not just the innermost one.
+    let stats = Arc::new(StatsTracker::with_inclusive_stats());
+    AllocationRegistry::set_global_tracker(Arc::clone(&stats))
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    let request = AllocationGroupToken::register().expect("failed to register allocation group");
+    let parse = AllocationGroupToken::register().expect("failed to register allocation group");
+    let render = AllocationGroupToken::register().expect("failed to register allocation group");
+
+    let mut retained = Vec::with_capacity(3);
+    request.in_scope(|| {
+        retained.push(vec![0u8; 100]);
+        parse.in_scope(|| retained.push(vec![0u8; 1_000]));
+        render.in_scope(|| {
+            retained.push(vec![0u8; 10_000]);
+
+            // Re-entering a group that's already active doesn't credit it twice.
+            request.in_scope(|| retained.push(vec![0u8; 5]));
+        });
+    });
+
+    AllocationRegistry::disable_tracking();
+
+    for (name, id) in [
+        ("request", request.id()),
+        ("parse", parse.id()),
+        ("render", render.id()),
+    ] {
+        let exclusive = stats.group(&id).map_or(0, |stats| stats.live_bytes());
+        let inclusive = stats
+            .group_inclusive(&id)
+            .map_or(0, |stats| stats.live_bytes());
+        println!(
+            "{:>8}: {:>6} bytes exclusive, {:>6} bytes inclusive",
+            name, exclusive, inclusive
+        );
+    }
+
+    drop(retained);
+}
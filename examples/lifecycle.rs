@@ -0,0 +1,98 @@
+use tracking_allocator::{
+    AllocationGroupId, AllocationGroupToken, AllocationRegistry, AllocationTracker, Allocator,
+};
+
+use std::{
+    alloc::System,
+    sync::{
+        mpsc::{sync_channel, SyncSender},
+        Mutex,
+    },
+    time::Instant,
+};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+enum LifecycleEvent {
+    Registered(AllocationGroupId, String),
+    Entered(AllocationGroupId),
+    Exited(AllocationGroupId),
+    Released(AllocationGroupId),
+}
+
+// A tracker that builds a timeline of allocation group activity, rather than tracking individual
+// allocations.  As with allocation events, the events are sent over a bounded channel so that the
+// tracker itself never blocks on anything that might allocate.
+struct TimelineTracker {
+    start: Instant,
+    sender: Mutex<SyncSender<(u128, LifecycleEvent)>>,
+}
+
+impl TimelineTracker {
+    fn send(&self, event: LifecycleEvent) {
+        let elapsed = self.start.elapsed().as_micros();
+        if let Ok(sender) = self.sender.lock() {
+            let _ = sender.try_send((elapsed, event));
+        }
+    }
+}
+
+impl AllocationTracker for TimelineTracker {
+    fn allocated(&self, _addr: usize, _size: usize, _group_id: AllocationGroupId) {}
+
+    fn deallocated(&self, _addr: usize, _current_group_id: AllocationGroupId) {}
+
+    fn registered(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        let name = tags
+            .iter()
+            .find(|(key, _)| key == "name")
+            .map_or_else(String::new, |(_, value)| value.clone());
+        self.send(LifecycleEvent::Registered(group_id, name));
+    }
+
+    fn entered(&self, group_id: AllocationGroupId) {
+        self.send(LifecycleEvent::Entered(group_id));
+    }
+
+    fn exited(&self, group_id: AllocationGroupId) {
+        self.send(LifecycleEvent::Exited(group_id));
+    }
+
+    fn released(&self, group_id: AllocationGroupId, _tags: &[(String, String)]) {
+        self.send(LifecycleEvent::Released(group_id));
+    }
+}
+
+fn main() {
+    let (sender, receiver) = sync_channel(64);
+    AllocationRegistry::set_global_tracker(TimelineTracker {
+        start: Instant::now(),
+        sender: Mutex::new(sender),
+    })
+    .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    let outer = AllocationGroupToken::register_with_tags([("name", "outer")])
+        .expect("failed to register allocation group");
+    outer.in_scope(|| {
+        let inner = AllocationGroupToken::register_with_tags([("name", "inner")])
+            .expect("failed to register allocation group");
+        inner.in_scope(|| vec![0u8; 1024].len());
+    });
+    drop(outer);
+
+    AllocationRegistry::disable_tracking();
+
+    for (elapsed, event) in receiver.try_iter() {
+        let description = match event {
+            LifecycleEvent::Registered(id, name) => {
+                format!("registered group {} ({})", id.as_usize(), name)
+            }
+            LifecycleEvent::Entered(id) => format!("entered group {}", id.as_usize()),
+            LifecycleEvent::Exited(id) => format!("exited group {}", id.as_usize()),
+            LifecycleEvent::Released(id) => format!("released group {}", id.as_usize()),
+        };
+        println!("{:>6}us  {}", elapsed, description);
+    }
+}
@@ -0,0 +1,70 @@
+use tracking_allocator::{AllocationGroupToken, AllocationRegistry, Allocator, StatsTracker};
+
+use std::{alloc::System, sync::Arc};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    let stats = Arc::new(StatsTracker::new());
+    AllocationRegistry::set_global_tracker(Arc::clone(&stats))
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    // Build a small tree of allocation groups: a service, with a request handler nested within it,
+    // and a parser nested within the request handler.
+    let service = AllocationGroupToken::register_with_tags([("name", "service")])
+        .expect("failed to register allocation group");
+    let handler =
+        AllocationGroupToken::register_child_with_tags(&service.id(), [("name", "handler")])
+            .expect("failed to register allocation group");
+    let parser =
+        AllocationGroupToken::register_child_with_tags(&handler.id(), [("name", "parser")])
+            .expect("failed to register allocation group");
+
+    let mut retained = Vec::new();
+    service.in_scope(|| {
+        retained.push(vec![0u8; 100]);
+        handler.in_scope(|| {
+            retained.push(vec![0u8; 1_000]);
+            parser.in_scope(|| retained.push(vec![0u8; 10_000]));
+        });
+    });
+
+    AllocationRegistry::disable_tracking();
+
+    let ancestry = parser
+        .id()
+        .ancestors()
+        .map(|id| id.as_usize().to_string())
+        .collect::<Vec<_>>();
+    println!(
+        "parser is group {}, nested within groups {}",
+        parser.id().as_usize(),
+        ancestry.join(" -> ")
+    );
+
+    // Each group on its own only covers what was allocated while it was the innermost group, while
+    // the rolled up statistics cover everything allocated within the group and its descendants.
+    let own = stats.snapshot();
+    for rolled_up in stats.snapshot_rolled_up() {
+        let name = rolled_up
+            .group_id
+            .tags()
+            .iter()
+            .find(|(key, _)| key == "name")
+            .map_or_else(|| "root".to_string(), |(_, value)| value.clone());
+        let own_bytes = own
+            .iter()
+            .find(|stats| stats.group_id == rolled_up.group_id)
+            .map_or(0, |stats| stats.live_bytes());
+        println!(
+            "{:>8}: {:>6} live bytes of its own, {:>6} including descendants",
+            name,
+            own_bytes,
+            rolled_up.live_bytes()
+        );
+    }
+
+    drop(retained);
+}
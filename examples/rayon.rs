@@ -0,0 +1,50 @@
+use rayon::prelude::*;
+use tracking_allocator::{
+    rayon::ParallelIteratorExt, AllocationGroupToken, AllocationRegistry, Allocator, StatsTracker,
+};
+
+use std::{alloc::System, sync::Arc};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    let stats = Arc::new(StatsTracker::new());
+    AllocationRegistry::set_global_tracker(Arc::clone(&stats))
+        .expect("no other global tracker should be set yet");
+
+    let items = (0..1000).collect::<Vec<usize>>();
+    AllocationRegistry::enable_tracking();
+
+    let plain = AllocationGroupToken::register().expect("failed to register allocation group");
+    let plain_id = plain.id();
+    plain.in_scope(|| {
+        // Without any help, the work done on rayon's pool threads isn't attributed to our group,
+        // apart from whatever happens to run on this thread.
+        let total: usize = items.par_iter().map(|i| vec![0u8; *i].len()).sum();
+        assert!(total > 0);
+    });
+
+    let wrapped = AllocationGroupToken::register().expect("failed to register allocation group");
+    let wrapped_id = wrapped.id();
+    wrapped.in_scope(|| {
+        // With `in_current_group`, the pool threads enter our group while running the iterator.
+        let total: usize = items
+            .par_iter()
+            .in_current_group()
+            .map(|i| vec![0u8; *i].len())
+            .sum();
+        assert!(total > 0);
+
+        // `join` does the same for a pair of closures.
+        let (a, b) = tracking_allocator::rayon::join(|| vec![0u8; 1000], || vec![0u8; 1000]);
+        assert_eq!(a.len() + b.len(), 2000);
+    });
+
+    AllocationRegistry::disable_tracking();
+
+    for (name, id) in [("plain", plain_id), ("wrapped", wrapped_id)] {
+        let allocated = stats.group(&id).map_or(0, |stats| stats.allocated_bytes);
+        println!("{} group allocated {} bytes", name, allocated);
+    }
+}
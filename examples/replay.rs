@@ -0,0 +1,32 @@
+use tracking_allocator::{EventLogReader, Replay};
+
+use std::{alloc::System, env, fs::File, io::BufReader};
+
+fn main() {
+    // Grab the event log to replay, such as the one written by the `event_log` example.
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "allocations.talog".to_string());
+    let file = File::open(&path).expect("failed to open event log");
+    let reader =
+        EventLogReader::new(BufReader::new(file)).expect("failed to read event log header");
+
+    // Load the whole log up front, so that reading it doesn't get in the way of the replay itself.
+    let replay = Replay::from_events(reader).expect("failed to read event log");
+
+    // Any `GlobalAlloc` can be replayed against, so comparing allocators is just a matter of
+    // passing in something like `jemallocator::Jemalloc` or `mimalloc::MiMalloc` instead.
+    let report = replay.run(&System);
+
+    println!(
+        "replayed {} allocations and {} deallocations from {} in {:?}",
+        report.allocations, report.deallocations, path, report.elapsed
+    );
+    match report.peak_rss {
+        Some(peak_rss) => println!("peak RSS: {} bytes", peak_rss),
+        None => println!("peak RSS: unavailable on this platform"),
+    }
+    if report.failed_allocations > 0 {
+        println!("{} allocations failed", report.failed_allocations);
+    }
+}
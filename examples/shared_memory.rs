@@ -0,0 +1,84 @@
+use tracking_allocator::{
+    AllocationEvent, AllocationGroupToken, AllocationRegistry, Allocator, SharedMemoryReader,
+    SharedMemoryTracker,
+};
+
+use std::{
+    alloc::System,
+    env,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // `SharedMemoryTracker` writes events into a ring buffer in a memory-mapped file, which is best
+    // kept on a `tmpfs` like `/dev/shm`, so that it never hits the disk.
+    let path = env::temp_dir().join("tracking-allocator.shm");
+    let tracker =
+        SharedMemoryTracker::create(&path, 64 * 1024).expect("failed to create ring buffer");
+
+    // The reader would normally live in another process entirely, but to keep things simple, we
+    // just read from a background thread here.  Tracking hasn't been enabled yet, so the reader's
+    // own allocations aren't tracked.
+    let mut reader = SharedMemoryReader::open(&path).expect("failed to open ring buffer");
+    let done = Arc::new(AtomicBool::new(false));
+    let reader_done = Arc::clone(&done);
+    let reader_thread = thread::spawn(move || {
+        let mut events = Vec::with_capacity(64 * 1024);
+        let (mut allocations, mut deallocations) = (0, 0);
+        loop {
+            let finished = reader_done.load(Ordering::Acquire);
+
+            events.clear();
+            reader
+                .read_available(&mut events)
+                .expect("failed to read ring buffer");
+            for event in &events {
+                match event {
+                    AllocationEvent::Allocated { .. } => allocations += 1,
+                    AllocationEvent::Deallocated { .. } => deallocations += 1,
+                }
+            }
+
+            if finished {
+                return (allocations, deallocations, reader.dropped_events());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+
+    AllocationRegistry::set_global_tracker(tracker)
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    // Do some allocating from a few threads, within an allocation group.
+    let workers = (0..4)
+        .map(|i| {
+            thread::spawn(move || {
+                let token =
+                    AllocationGroupToken::register().expect("failed to register allocation group");
+                let _guard = token.enter();
+                let data = (0..1000).map(|j| vec![i as u8; j]).collect::<Vec<_>>();
+                data.len()
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().expect("worker panicked");
+    }
+
+    AllocationRegistry::disable_tracking();
+    done.store(true, Ordering::Release);
+    let (allocations, deallocations, dropped) = reader_thread.join().expect("reader panicked");
+
+    println!(
+        "read {} allocations and {} deallocations from the ring buffer, {} events dropped",
+        allocations, deallocations, dropped
+    );
+    let _ = std::fs::remove_file(&path);
+}
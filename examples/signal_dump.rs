@@ -0,0 +1,40 @@
+use tracking_allocator::{
+    AllocationGroupToken, AllocationRegistry, Allocator, SignalDump, StatsTracker,
+};
+
+use std::{alloc::System, process, sync::Arc, thread, time::Duration};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // `SignalDump` reads the per-group statistics aggregated by a `StatsTracker`, so we install
+    // one of those as the global tracker, keeping a clone of it around.
+    let stats = Arc::new(StatsTracker::new());
+    AllocationRegistry::set_global_tracker(Arc::clone(&stats))
+        .expect("no other global tracker should be set yet");
+
+    // From now on, sending `SIGUSR1` to the process dumps the statistics to standard error.
+    let handle = SignalDump::new(stats)
+        .spawn()
+        .expect("failed to install signal handler");
+    AllocationRegistry::enable_tracking();
+
+    // Hold on to some memory within a tagged allocation group, so that it shows up in the leak
+    // report.  The token has to be kept around too, as the group is released once it's dropped.
+    let token = AllocationGroupToken::register_with_tags([("component", "cache")])
+        .expect("failed to register allocation group");
+    let cache = token.in_scope(|| (0..100).map(|i| vec![0u8; i * 16]).collect::<Vec<_>>());
+
+    // Normally you'd run `kill -USR1 <pid>` from a shell, but we just signal ourselves here.
+    eprintln!("sending SIGUSR1 to process {}", process::id());
+    unsafe { libc::raise(libc::SIGUSR1) };
+
+    // Give the background thread a chance to notice the signal before we shut it down.
+    thread::sleep(Duration::from_millis(500));
+
+    AllocationRegistry::disable_tracking();
+    handle.finish().expect("failed to dump statistics");
+    drop(cache);
+    drop(token);
+}
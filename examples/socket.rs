@@ -0,0 +1,39 @@
+use tracking_allocator::{AllocationGroupToken, AllocationRegistry, Allocator, SocketTracker};
+
+use std::{alloc::System, env, thread};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // `SocketTracker` streams allocation events to a collector in another process, so start one
+    // first with `cargo run --bin tracking-allocator-collector -- /tmp/tracking-allocator.sock`.
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/tmp/tracking-allocator.sock".to_string());
+    let (tracker, handle) = SocketTracker::connect(&path).expect("failed to connect to collector");
+    AllocationRegistry::set_global_tracker(tracker)
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    // Do some allocating from a few threads, within an allocation group.
+    let workers = (0..4)
+        .map(|i| {
+            thread::spawn(move || {
+                let token =
+                    AllocationGroupToken::register().expect("failed to register allocation group");
+                let _guard = token.enter();
+                let data = (0..1000).map(|j| vec![i as u8; j]).collect::<Vec<_>>();
+                data.len()
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().expect("worker panicked");
+    }
+
+    // Finishing the handle sends anything still buffered, and closes the connection.  If the
+    // collector couldn't keep up, some events were dropped rather than slowing us down.
+    AllocationRegistry::disable_tracking();
+    handle.finish().expect("failed to stream events");
+}
@@ -0,0 +1,63 @@
+use tracking_allocator::{
+    spawn_in_current_group, AllocationGroupId, AllocationGroupToken, AllocationRegistry,
+    AllocationTracker, Allocator, ThreadBuilderExt,
+};
+
+use std::{
+    alloc::System,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+static GROUP_ONE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingTracker;
+
+impl AllocationTracker for CountingTracker {
+    fn allocated(&self, _addr: usize, size: usize, group_id: AllocationGroupId) {
+        if group_id.as_usize() == 1 {
+            GROUP_ONE_BYTES.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    fn deallocated(&self, _addr: usize, _current_group_id: AllocationGroupId) {}
+}
+
+fn main() {
+    AllocationRegistry::set_global_tracker(CountingTracker)
+        .expect("no other global tracker should be set yet");
+    AllocationRegistry::enable_tracking();
+
+    let token = AllocationGroupToken::register().expect("failed to register allocation group");
+    token.in_scope(|| {
+        // A thread spawned with `thread::spawn` doesn't inherit our allocation group, so this
+        // allocation is attributed to the root allocation group.
+        thread::spawn(|| vec![0u8; 1000])
+            .join()
+            .expect("thread panicked");
+        println!(
+            "after thread::spawn: {} bytes in group 1",
+            GROUP_ONE_BYTES.load(Ordering::Relaxed)
+        );
+
+        // These threads do inherit it, so their allocations are attributed to group 1.
+        spawn_in_current_group(|| vec![0u8; 1000])
+            .join()
+            .expect("thread panicked");
+        thread::Builder::new()
+            .name("worker".to_string())
+            .spawn_in_current_group(|| vec![0u8; 1000])
+            .expect("failed to spawn thread")
+            .join()
+            .expect("thread panicked");
+        println!(
+            "after spawn_in_current_group: {} bytes in group 1",
+            GROUP_ONE_BYTES.load(Ordering::Relaxed)
+        );
+    });
+
+    AllocationRegistry::disable_tracking();
+}
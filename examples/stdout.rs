@@ -84,7 +84,7 @@ fn main() {
 
     // Create and set our allocation tracker.  Even with the tracker set, we're still not tracking
     // allocations yet.  We need to enable tracking explicitly.
-    let _ = AllocationRegistry::set_global_tracker(ChannelBackedTracker::from(tx))
+    AllocationRegistry::set_global_tracker(ChannelBackedTracker::from(tx))
         .expect("no other global tracker should be set yet");
 
     AllocationRegistry::enable_tracking();
@@ -95,19 +95,21 @@ fn main() {
     // allocating storage to do so -- without ending up in a weird re-entrant situation if we just
     // instrumented all allocations throughout the process.
     //
-    // Callers can attach tags to their group by calling `AllocationRegistry::register_with_tags`
+    // Callers can attach tags to their group by calling `AllocationGroupToken::register_with_tags`
     // instead, but we're going to register our group without any tags for now.
     let local_token =
         AllocationGroupToken::register().expect("failed to register allocation group");
 
     // Now, get an allocation guard from our token.  This guard ensures the allocation group is
     // marked as the current allocation group, so that our allocations are properly associated.
+    //
+    // If we only needed the group for a single block of code, `AllocationGroupToken::in_scope`
+    // would run a closure within the group without giving up the token.
     let local_guard = local_token.enter();
 
     // Now we can finally make some allocations!
     let s = String::from("Hello world!");
-    let mut v = Vec::new();
-    v.push(s);
+    let v = vec![s];
 
     // Drop our "local" group guard.  You can also call `exit` on `AllocationGuard` to transform it
     // back to an `AllocationToken` for further reuse.  Exiting/dropping the guard will update the
@@ -129,8 +131,7 @@ fn main() {
     // This simply means they were deallocated within the global allocation group (aka not actually
     // within a registered allocation group) but you would still need to track addresses to group
     // IDs on your own to know who "owns" an allocation.
-    let mut events = rx.try_iter();
-    while let Some(event) = events.next() {
+    for event in rx.try_iter() {
         match event {
             AllocationEvent::Allocated {
                 addr,
@@ -0,0 +1,52 @@
+use tracking_allocator::{
+    AllocationGroupToken, AllocationRegistry, Allocator, StatsTracker, TimeSeriesSampler,
+};
+
+use std::{alloc::System, sync::Arc, thread, time::Duration};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+fn main() {
+    // `TimeSeriesSampler` reads the per-group statistics aggregated by a `StatsTracker`, so we
+    // install one of those as the global tracker, keeping a clone of it around.
+    let stats = Arc::new(StatsTracker::new());
+    AllocationRegistry::set_global_tracker(Arc::clone(&stats))
+        .expect("no other global tracker should be set yet");
+
+    // Sample every 10ms, keeping the last 50 samples of each group.
+    let (series, handle) = TimeSeriesSampler::new(stats)
+        .with_interval(Duration::from_millis(10))
+        .with_capacity(50)
+        .spawn()
+        .expect("failed to spawn sampler");
+    AllocationRegistry::enable_tracking();
+
+    // Grow and then shrink some memory within an allocation group, so that there's something
+    // interesting to look at.
+    let token = AllocationGroupToken::register().expect("failed to register allocation group");
+    let group_id = token.id();
+    let guard = token.enter();
+    let mut buffers = Vec::new();
+    for i in 0..20 {
+        buffers.push(vec![0u8; 4096]);
+        if i >= 10 {
+            buffers.truncate(buffers.len() - 2);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(buffers);
+    drop(guard);
+
+    AllocationRegistry::disable_tracking();
+    handle.finish().expect("failed to stop sampler");
+
+    // The time series can still be read once the sampler has stopped.
+    for sample in series.history(&group_id) {
+        println!(
+            "{:>8.1}ms  {:>8} bytes",
+            sample.elapsed.as_secs_f64() * 1000.0,
+            sample.live_bytes
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use tracking_allocator::{AllocationRegistry, Allocator, StatsTracker};
+
+use std::{alloc::System, sync::Arc, time::Duration};
+
+#[global_allocator]
+static GLOBAL: Allocator<System> = Allocator::system();
+
+async fn handle_request(payload_size: usize) -> usize {
+    let payload = vec![0u8; payload_size];
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    payload.len()
+}
+
+fn main() {
+    // `StatsTracker` aggregates statistics per allocation group, and since every task gets its own
+    // allocation group, that means per task.
+    let stats = Arc::new(StatsTracker::new());
+    AllocationRegistry::set_global_tracker(Arc::clone(&stats))
+        .expect("no other global tracker should be set yet");
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_time()
+        .build()
+        .expect("failed to build runtime");
+
+    AllocationRegistry::enable_tracking();
+    runtime.block_on(async {
+        // Each task is tagged with its name, which the allocation group can be looked up by later.
+        let tasks = (0..100)
+            .map(|i| {
+                let name = if i % 10 == 0 { "upload" } else { "ping" };
+                let size = if i % 10 == 0 { 64 * 1024 } else { 64 };
+                tracking_allocator::tokio::spawn_named(name, handle_request(size))
+            })
+            .collect::<Vec<_>>();
+
+        // Find the running tasks which are using the most memory.  The allocation group of a task
+        // is released once it completes, so this has to be done while they're still running.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut snapshot = stats
+            .snapshot()
+            .into_iter()
+            .filter(|stats| !stats.group_id.tags().is_empty())
+            .collect::<Vec<_>>();
+        snapshot.sort_by_key(|stats| std::cmp::Reverse(stats.live_bytes()));
+        for stats in snapshot.iter().take(5) {
+            let tags = stats.group_id.tags();
+            println!(
+                "task '{}' (group {}) is using {} bytes",
+                tags[0].1,
+                stats.group_id,
+                stats.live_bytes()
+            );
+        }
+
+        for task in tasks {
+            task.await.expect("task panicked");
+        }
+    });
+    AllocationRegistry::disable_tracking();
+
+    // Once the tasks have completed, their statistics are folded into those of the retired groups.
+    let retired = stats.retired();
+    println!(
+        "{} completed tasks allocated {} bytes in total",
+        retired.groups, retired.allocated_bytes
+    );
+}
@@ -98,28 +98,23 @@ fn main() {
 
     // We spawn off our processing thread so the channels don't back up as we're executing.
     let _ = thread::spawn(move || {
-        loop {
-            // We're only using a timeout here so that we ensure that we're checking to see if we
-            // should actually finish up and exit.
-            if let Ok(event) = rx.recv_timeout(Duration::from_millis(10)) {
-                match event {
-                    AllocationEvent::Allocated {
-                        addr,
-                        size,
-                        group_id,
-                    } => {
-                        println!(
-                            "allocation -> addr={:#x} size={} group_id={:?}",
-                            addr, size, group_id
-                        );
-                    }
-                    AllocationEvent::Deallocated { addr, group_id } => {
-                        println!("deallocation -> addr={:#x} group_id={:?}", addr, group_id);
-                    }
+        // We're only using a timeout here so that we ensure that we're checking to see if we
+        // should actually finish up and exit.  Once no more events arrive, we're done.
+        while let Ok(event) = rx.recv_timeout(Duration::from_millis(10)) {
+            match event {
+                AllocationEvent::Allocated {
+                    addr,
+                    size,
+                    group_id,
+                } => {
+                    println!(
+                        "allocation -> addr={:#x} size={} group_id={:?}",
+                        addr, size, group_id
+                    );
+                }
+                AllocationEvent::Deallocated { addr, group_id } => {
+                    println!("deallocation -> addr={:#x} group_id={:?}", addr, group_id);
                 }
-            } else {
-                // No more events, we're done.
-                break;
             }
 
             // NOTE: Since the global tracker holds the sender side of the channel, if we just did
@@ -148,7 +143,7 @@ fn main() {
 
     // Create and set our allocation tracker.  Even with the tracker set, we're still not tracking
     // allocations yet.  We need to enable tracking explicitly.
-    let _ = AllocationRegistry::set_global_tracker(ChannelBackedTracker::from(tx))
+    AllocationRegistry::set_global_tracker(ChannelBackedTracker::from(tx))
         .expect("no other global tracker should be set yet");
 
     // Register two allocation groups.  Allocation groups are what allocations are associated with.
@@ -199,8 +194,8 @@ fn main() {
         let handle1 = tokio::spawn(task1);
         let handle2 = tokio::spawn(task2);
 
-        let _ = handle1.await.expect("task1 panicked unexpectedly");
-        let _ = handle2.await.expect("task2 panicked unexpectedly");
+        handle1.await.expect("task1 panicked unexpectedly");
+        handle2.await.expect("task2 panicked unexpectedly");
     });
 
     // Disable tracking and read the allocation events from our receiver.
@@ -233,7 +228,7 @@ async fn ping_pong(
     while counter > 0 {
         // We allocate this vector on our side, and send it to the other task to be deallocated.
         let buf: Vec<String> = Vec::with_capacity(buf_size);
-        let _ = tx.send(buf).await.expect("tx send should not fail");
+        tx.send(buf).await.expect("tx send should not fail");
 
         // We receive another buffer from the other, and deallocate it for them.
         let their_buf = rx.recv().await.expect("rx recv should not be empty");
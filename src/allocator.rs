@@ -1,21 +1,59 @@
-use std::alloc::{GlobalAlloc, Layout, System};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    ptr::NonNull,
+};
 
-use crate::get_global_tracker;
+use crate::canary::{report_corruption, CanaryLayout};
 use crate::token::get_active_allocation_group_id;
+use crate::{get_global_tracker, with_global_tracker, SuspendGuard};
 
 /// Tracking allocator implementation.
 ///
 /// This allocator must be installed via `#[global_allocator]` in order to take effect.  More
 /// information on using this allocator can be found in the examples, or directly in the standard
 /// library docs for [`GlobalAlloc`].
+///
+/// ## Buffer overflow detection
+///
+/// When created with [`with_canaries`][Allocator::with_canaries], every allocation is padded with
+/// canary bytes before and after the region handed back to the caller.  The canaries are verified
+/// when the allocation is deallocated, and if either has been overwritten, the corruption is
+/// reported via [`AllocationTracker::corrupted`][crate::AllocationTracker::corrupted].
 pub struct Allocator<A> {
     inner: A,
+    canaries: bool,
 }
 
 impl<A> Allocator<A> {
     /// Creates a new `Allocator` that wraps another allocator.
     pub const fn from_allocator(allocator: A) -> Self {
-        Self { inner: allocator }
+        Self {
+            inner: allocator,
+            canaries: false,
+        }
+    }
+
+    /// Creates a new `Allocator` that wraps another allocator, with buffer overflow detection enabled.
+    ///
+    /// Each allocation is surrounded with canary bytes, which are verified on deallocation.
+    ///
+    /// Each allocation grows by 32 bytes of canaries, plus the size of an
+    /// [`AllocationGroupId`][crate::AllocationGroupId] -- 48 bytes in total on 64-bit targets, and
+    /// more for allocations with a large alignment -- as the canaries, and the allocation group the
+    /// allocation was made under, are stored inline.
+    ///
+    /// If an allocation's canaries are found to be overwritten when it is deallocated, the global
+    /// tracker is notified via [`AllocationTracker::corrupted`][crate::AllocationTracker::corrupted].
+    /// If no tracker is set, or tracking is disabled, the corruption is written to standard error
+    /// and the process is aborted.
+    ///
+    /// As the layout of every allocation changes, this can only be chosen when the allocator is
+    /// created, and not toggled at runtime.
+    pub const fn with_canaries(allocator: A) -> Self {
+        Self {
+            inner: allocator,
+            canaries: true,
+        }
     }
 }
 
@@ -35,34 +73,84 @@ impl Default for Allocator<System> {
 unsafe impl<A: GlobalAlloc> GlobalAlloc for Allocator<A> {
     #[track_caller]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.canaries {
+            return self.alloc_with_canaries(layout);
+        }
+
         let size = layout.size();
         let ptr = self.inner.alloc(layout);
         let addr = ptr as usize;
 
-        // TODO: Should we have a thread-local that stops reentrant tracking of allocations?  This
-        // would make it much, much easier to actually accomplish the storage of allocation data for
-        // tallying and analysis since we need data structures that grow over time, and might
-        // register new allocation groups over time as well, requiring resizing and so on.
-        //
-        // Biggest downside is that it's potentially another chunk of overhead that each call must
-        // endure as we set and unset the "don't track reentrant-ly" flag.  We should do it and
-        // benchmark, though.
-        if let Some(tracker) = get_global_tracker() {
+        // Tracking is suspended while the tracker runs, so any allocations it makes will not be
+        // tracked, and cannot recurse back into it.
+        with_global_tracker(|tracker| {
             let group_id = get_active_allocation_group_id();
             tracker.allocated(addr, size, group_id);
-        }
+        });
 
         ptr
     }
 
     #[track_caller]
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.canaries {
+            return self.dealloc_with_canaries(ptr, layout);
+        }
+
+        // We notify the tracker before actually deallocating, as otherwise another thread could
+        // be handed the same address, and report its allocation, before we report our deallocation.
         let addr = ptr as usize;
+        with_global_tracker(|tracker| {
+            let group_id = get_active_allocation_group_id();
+            tracker.deallocated(addr, group_id);
+        });
+
         self.inner.dealloc(ptr, layout);
+    }
+}
+
+impl<A: GlobalAlloc> Allocator<A> {
+    #[inline(never)]
+    unsafe fn alloc_with_canaries(&self, layout: Layout) -> *mut u8 {
+        let canary_layout = match CanaryLayout::new(layout) {
+            Some(canary_layout) => canary_layout,
+            None => return std::ptr::null_mut(),
+        };
+        let outer_ptr = match NonNull::new(self.inner.alloc(canary_layout.outer())) {
+            Some(outer_ptr) => outer_ptr,
+            None => return std::ptr::null_mut(),
+        };
+
+        let group_id = get_active_allocation_group_id();
+        let ptr = canary_layout.initialize(outer_ptr, group_id);
+
+        with_global_tracker(|tracker| tracker.allocated(ptr as usize, layout.size(), group_id));
+
+        ptr
+    }
+
+    #[inline(never)]
+    unsafe fn dealloc_with_canaries(&self, ptr: *mut u8, layout: Layout) {
+        let addr = ptr as usize;
+        let canary_layout =
+            CanaryLayout::new(layout).expect("layout was valid when the allocation was made");
 
-        if let Some(tracker) = get_global_tracker() {
+        if let Err(group_id) = canary_layout.check(ptr) {
+            match get_global_tracker() {
+                Some(tracker) => {
+                    let _suspended = SuspendGuard::suspend();
+                    tracker.corrupted(addr, layout.size(), group_id)
+                }
+                None => report_corruption(addr, layout.size(), group_id),
+            }
+        }
+
+        with_global_tracker(|tracker| {
             let group_id = get_active_allocation_group_id();
             tracker.deallocated(addr, group_id);
-        }
+        });
+
+        self.inner
+            .dealloc(canary_layout.outer_ptr(ptr), canary_layout.outer());
     }
 }
@@ -0,0 +1,425 @@
+//! Offline analysis of allocation event logs recorded by `EventLogTracker`.
+//!
+//! Reads a log, and prints per-group summaries, a timeline of peak usage, the groups with the most
+//! live memory at the end of the log, a histogram of allocation lifetimes, and the distribution of
+//! allocation sizes.  Passing `--json` prints the same analysis as a single JSON object instead.
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::Write as _,
+    fs::File,
+    io, process,
+};
+
+use tracking_allocator::{AllocationEvent, AllocationGroupId, EventLogReader};
+
+const USAGE: &str = "\
+usage: tracking-allocator-analyze [--json] [--top N] [--buckets N] <LOG>
+
+options:
+    --json         print the analysis as JSON
+    --top N        number of leaking groups to show (default: 10)
+    --buckets N    number of buckets in the peak usage timeline (default: 20)
+";
+
+/// Upper bounds, in nanoseconds, of each lifetime histogram bucket.
+const LIFETIME_BUCKETS: &[(u64, &str)] = &[
+    (1_000, "<1us"),
+    (10_000, "<10us"),
+    (100_000, "<100us"),
+    (1_000_000, "<1ms"),
+    (10_000_000, "<10ms"),
+    (100_000_000, "<100ms"),
+    (1_000_000_000, "<1s"),
+    (10_000_000_000, "<10s"),
+    (u64::MAX, ">=10s"),
+];
+
+struct Options {
+    json: bool,
+    top: usize,
+    buckets: usize,
+    path: String,
+}
+
+#[derive(Default)]
+struct GroupSummary {
+    allocations: u64,
+    deallocations: u64,
+    allocated_bytes: u64,
+    freed_bytes: u64,
+    live_bytes: u64,
+    live_allocations: u64,
+    peak_bytes: u64,
+    peak_timestamp: u64,
+}
+
+struct Live {
+    group_id: AllocationGroupId,
+    size: u64,
+    timestamp: u64,
+}
+
+#[derive(Default)]
+struct Analysis {
+    events: u64,
+    unmatched_deallocations: u64,
+    duration: u64,
+    live_bytes: u64,
+    peak_bytes: u64,
+    peak_timestamp: u64,
+    groups: BTreeMap<AllocationGroupId, GroupSummary>,
+    // Peak live bytes within each of the equally-sized time ranges of the timeline.
+    timeline: Vec<u64>,
+    // Number of freed allocations whose lifetime fell within each lifetime histogram bucket.
+    lifetimes: Vec<u64>,
+    size_classes: BTreeMap<u64, u64>,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match analyze(&options.path, options.buckets) {
+        Ok(analysis) => {
+            let output = if options.json {
+                render_json(&analysis, &options)
+            } else {
+                render_text(&analysis, &options)
+            };
+            print!("{}", output);
+        }
+        Err(e) => {
+            eprintln!("error: failed to read {}: {}", options.path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options {
+        json: false,
+        top: 10,
+        buckets: 20,
+        path: String::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => options.json = true,
+            "--top" => options.top = parse_count(&arg, args.next())?,
+            "--buckets" => options.buckets = parse_count(&arg, args.next())?.max(1),
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if options.path.is_empty() => options.path = arg,
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    if options.path.is_empty() {
+        return Err("no log file given".to_string());
+    }
+    Ok(options)
+}
+
+fn parse_count(option: &str, value: Option<String>) -> Result<usize, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("'{}' requires a number", option))
+}
+
+fn analyze(path: &str, buckets: usize) -> io::Result<Analysis> {
+    // We need to know how long the log spans before we can place events on the timeline, so we
+    // make a quick first pass to find the timestamp of the last event.
+    let mut duration = 0;
+    for event in EventLogReader::new(File::open(path)?)? {
+        duration = event?.timestamp();
+    }
+    let bucket_width = (duration / buckets as u64).max(1);
+
+    let mut analysis = Analysis {
+        duration,
+        timeline: vec![0; buckets],
+        lifetimes: vec![0; LIFETIME_BUCKETS.len()],
+        ..Default::default()
+    };
+    let mut live = HashMap::new();
+    let mut current_bucket = 0;
+    for event in EventLogReader::new(File::open(path)?)? {
+        let event = event?;
+        let timestamp = event.timestamp();
+        analysis.events += 1;
+
+        // Usage stays where it was until the next event, so any time ranges without events of
+        // their own peak at whatever was live going into them.
+        let bucket = ((timestamp / bucket_width) as usize).min(buckets - 1);
+        while current_bucket < bucket {
+            current_bucket += 1;
+            analysis.timeline[current_bucket] = analysis.live_bytes;
+        }
+
+        match event {
+            AllocationEvent::Allocated {
+                timestamp,
+                addr,
+                size,
+                group_id,
+                ..
+            } => {
+                let size = size as u64;
+                live.insert(
+                    addr,
+                    Live {
+                        group_id,
+                        size,
+                        timestamp,
+                    },
+                );
+
+                let group = analysis.groups.entry(group_id).or_default();
+                group.allocations += 1;
+                group.allocated_bytes += size;
+                group.live_allocations += 1;
+                group.live_bytes += size;
+                if group.live_bytes > group.peak_bytes {
+                    group.peak_bytes = group.live_bytes;
+                    group.peak_timestamp = timestamp;
+                }
+
+                analysis.live_bytes += size;
+                if analysis.live_bytes > analysis.peak_bytes {
+                    analysis.peak_bytes = analysis.live_bytes;
+                    analysis.peak_timestamp = timestamp;
+                }
+
+                // Sizes above the largest power of two are counted in the top size class.
+                let class = size.max(1).checked_next_power_of_two().unwrap_or(u64::MAX);
+                *analysis.size_classes.entry(class).or_default() += 1;
+            }
+            AllocationEvent::Deallocated {
+                timestamp, addr, ..
+            } => match live.remove(&addr) {
+                Some(allocation) => {
+                    let group = analysis.groups.entry(allocation.group_id).or_default();
+                    group.deallocations += 1;
+                    group.freed_bytes += allocation.size;
+                    group.live_allocations -= 1;
+                    group.live_bytes -= allocation.size;
+
+                    analysis.live_bytes -= allocation.size;
+
+                    let lifetime = timestamp.saturating_sub(allocation.timestamp);
+                    let bucket = LIFETIME_BUCKETS
+                        .iter()
+                        .position(|(bound, _)| lifetime < *bound)
+                        .unwrap_or(LIFETIME_BUCKETS.len() - 1);
+                    analysis.lifetimes[bucket] += 1;
+                }
+                None => analysis.unmatched_deallocations += 1,
+            },
+        }
+
+        analysis.timeline[bucket] = analysis.timeline[bucket].max(analysis.live_bytes);
+    }
+
+    Ok(analysis)
+}
+
+impl Analysis {
+    /// Peak live bytes within each time range of the timeline, as (start, peak bytes).
+    fn timeline(&self) -> Vec<(u64, u64)> {
+        let width = (self.duration / self.timeline.len() as u64).max(1);
+        self.timeline
+            .iter()
+            .enumerate()
+            .map(|(i, peak)| (i as u64 * width, *peak))
+            .collect()
+    }
+
+    /// Groups with live allocations at the end of the log, ordered by live bytes, descending.
+    fn leaking_groups(&self, top: usize) -> Vec<(AllocationGroupId, &GroupSummary)> {
+        let mut leaking = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.live_allocations > 0)
+            .map(|(id, group)| (*id, group))
+            .collect::<Vec<_>>();
+        leaking.sort_by(|a, b| b.1.live_bytes.cmp(&a.1.live_bytes).then(a.0.cmp(&b.0)));
+        leaking.truncate(top);
+        leaking
+    }
+
+    fn lifetime_histogram(&self) -> Vec<(&'static str, u64)> {
+        LIFETIME_BUCKETS
+            .iter()
+            .zip(&self.lifetimes)
+            .map(|((_, label), count)| (*label, *count))
+            .collect()
+    }
+}
+
+fn render_text(analysis: &Analysis, options: &Options) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{} events over {}, peak usage {} bytes at {}",
+        analysis.events,
+        format_duration(analysis.duration),
+        analysis.peak_bytes,
+        format_duration(analysis.peak_timestamp)
+    );
+    if analysis.unmatched_deallocations > 0 {
+        let _ = writeln!(
+            out,
+            "{} deallocations of allocations made before recording started",
+            analysis.unmatched_deallocations
+        );
+    }
+
+    let _ = writeln!(out, "\nper-group summary:");
+    let _ = writeln!(
+        out,
+        "  {:>8} {:>10} {:>10} {:>14} {:>14} {:>14} {:>14}",
+        "group", "allocs", "deallocs", "allocated", "freed", "live", "peak"
+    );
+    for (id, group) in &analysis.groups {
+        let _ = writeln!(
+            out,
+            "  {:>8} {:>10} {:>10} {:>14} {:>14} {:>14} {:>14}",
+            id,
+            group.allocations,
+            group.deallocations,
+            group.allocated_bytes,
+            group.freed_bytes,
+            group.live_bytes,
+            group.peak_bytes
+        );
+    }
+
+    let _ = writeln!(out, "\npeak usage timeline:");
+    for (start, peak) in analysis.timeline() {
+        let _ = writeln!(out, "  {:>12}  {} bytes", format_duration(start), peak);
+    }
+
+    let _ = writeln!(out, "\ntop leaking groups:");
+    let leaking = analysis.leaking_groups(options.top);
+    if leaking.is_empty() {
+        let _ = writeln!(out, "  (none)");
+    }
+    for (id, group) in leaking {
+        let _ = writeln!(
+            out,
+            "  group {}: {} bytes in {} allocations",
+            id, group.live_bytes, group.live_allocations
+        );
+    }
+
+    let _ = writeln!(out, "\nallocation lifetimes:");
+    for (label, count) in analysis.lifetime_histogram() {
+        let _ = writeln!(out, "  {:>8}  {}", label, count);
+    }
+
+    let _ = writeln!(out, "\nallocation sizes:");
+    for (class, count) in &analysis.size_classes {
+        let _ = writeln!(out, "  {:>12}  {}", format!("<={}", class), count);
+    }
+
+    out
+}
+
+fn render_json(analysis: &Analysis, options: &Options) -> String {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "{{\"events\":{},\"unmatched_deallocations\":{},\"duration_ns\":{},\"peak_bytes\":{},\
+         \"peak_timestamp_ns\":{},\"groups\":[",
+        analysis.events,
+        analysis.unmatched_deallocations,
+        analysis.duration,
+        analysis.peak_bytes,
+        analysis.peak_timestamp
+    );
+    for (i, (id, group)) in analysis.groups.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(
+            out,
+            "{{\"group_id\":{},\"generation\":{},\"allocations\":{},\"deallocations\":{},\
+             \"allocated_bytes\":{},\"freed_bytes\":{},\"live_bytes\":{},\"live_allocations\":{},\
+             \"peak_bytes\":{},\"peak_timestamp_ns\":{}}}",
+            id.as_usize(),
+            id.generation(),
+            group.allocations,
+            group.deallocations,
+            group.allocated_bytes,
+            group.freed_bytes,
+            group.live_bytes,
+            group.live_allocations,
+            group.peak_bytes,
+            group.peak_timestamp
+        );
+    }
+
+    out.push_str("],\"timeline\":[");
+    for (i, (start, peak)) in analysis.timeline().iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{{\"start_ns\":{},\"peak_bytes\":{}}}", start, peak);
+    }
+
+    out.push_str("],\"leaking_groups\":[");
+    for (i, (id, group)) in analysis.leaking_groups(options.top).iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(
+            out,
+            "{{\"group_id\":{},\"generation\":{},\"live_bytes\":{},\"live_allocations\":{}}}",
+            id.as_usize(),
+            id.generation(),
+            group.live_bytes,
+            group.live_allocations
+        );
+    }
+
+    out.push_str("],\"lifetimes\":[");
+    for (i, (label, count)) in analysis.lifetime_histogram().iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{{\"bucket\":\"{}\",\"count\":{}}}", label, count);
+    }
+
+    out.push_str("],\"sizes\":[");
+    for (i, (class, count)) in analysis.size_classes.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{{\"max_size\":{},\"count\":{}}}", class, count);
+    }
+    out.push_str("]}\n");
+
+    out
+}
+
+fn format_duration(nanos: u64) -> String {
+    if nanos < 1_000 {
+        format!("{}ns", nanos)
+    } else if nanos < 1_000_000 {
+        format!("{:.1}us", nanos as f64 / 1e3)
+    } else if nanos < 1_000_000_000 {
+        format!("{:.1}ms", nanos as f64 / 1e6)
+    } else {
+        format!("{:.2}s", nanos as f64 / 1e9)
+    }
+}
@@ -0,0 +1,242 @@
+//! Out-of-process collector for allocation events streamed by `SocketTracker`.
+//!
+//! Listens on a Unix domain socket, accepts connections from tracked processes, and aggregates
+//! their events into per-connection, per-group statistics, which are printed periodically and
+//! whenever a connection closes.
+#[cfg(unix)]
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::Write as _,
+    io,
+    os::unix::net::{UnixListener, UnixStream},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use tracking_allocator::{AllocationEvent, AllocationGroupId, EventStreamReader};
+
+#[cfg(unix)]
+const USAGE: &str = "\
+usage: tracking-allocator-collector [--interval SECS] <SOCKET>
+
+options:
+    --interval SECS    how often to print statistics (default: 5)
+";
+
+#[cfg(unix)]
+struct Options {
+    interval: u64,
+    path: String,
+}
+
+#[cfg(unix)]
+#[derive(Default)]
+struct GroupSummary {
+    allocations: u64,
+    deallocations: u64,
+    allocated_bytes: u64,
+    freed_bytes: u64,
+    live_bytes: u64,
+}
+
+#[cfg(unix)]
+#[derive(Default)]
+struct Collector {
+    connections: u64,
+    active_connections: u64,
+    events: u64,
+    dropped_events: u64,
+    // Allocation groups are only unique within a process, so they're keyed by the connection they
+    // were seen on as well, with connections numbered from 1 in the order they were accepted.
+    groups: BTreeMap<(u64, AllocationGroupId), GroupSummary>,
+}
+
+#[cfg(unix)]
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let listener = match bind(&options.path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("error: failed to listen on {}: {}", options.path, e);
+            process::exit(1);
+        }
+    };
+    eprintln!("listening on {}", options.path);
+
+    let collector = Arc::new(Mutex::new(Collector::default()));
+
+    let printer = Arc::clone(&collector);
+    let interval = Duration::from_secs(options.interval);
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        print!("{}", render(&lock(&printer)));
+    });
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let collector = Arc::clone(&collector);
+                thread::spawn(move || {
+                    if let Err(e) = collect(stream, &collector) {
+                        eprintln!("error: connection failed: {}", e);
+                    }
+                    let mut collector = lock(&collector);
+                    collector.active_connections -= 1;
+                    print!("{}", render(&collector));
+                });
+            }
+            Err(e) => eprintln!("error: failed to accept connection: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("error: tracking-allocator-collector requires Unix domain sockets");
+    std::process::exit(1);
+}
+
+#[cfg(unix)]
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options {
+        interval: 5,
+        path: String::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval" => {
+                options.interval = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .filter(|interval| *interval > 0)
+                    .ok_or_else(|| format!("'{}' requires a positive number", arg))?;
+            }
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if options.path.is_empty() => options.path = arg,
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    if options.path.is_empty() {
+        return Err("no socket path given".to_string());
+    }
+    Ok(options)
+}
+
+#[cfg(unix)]
+fn bind(path: &str) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        // A socket left behind by a collector that has since exited can't be connected to, so we
+        // clean it up and try again.  One that can be connected to belongs to a live collector.
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() => {
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        result => result,
+    }
+}
+
+#[cfg(unix)]
+fn lock(collector: &Mutex<Collector>) -> std::sync::MutexGuard<'_, Collector> {
+    collector.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(unix)]
+fn collect(stream: UnixStream, collector: &Mutex<Collector>) -> io::Result<()> {
+    let connection = {
+        let mut collector = lock(collector);
+        collector.connections += 1;
+        collector.active_connections += 1;
+        collector.connections
+    };
+
+    let mut reader = EventStreamReader::new(stream)?;
+    let mut live = HashMap::new();
+    let mut dropped = 0;
+
+    while let Some(event) = reader.next() {
+        let event = event?;
+        let mut collector = lock(collector);
+        collector.events += 1;
+        collector.dropped_events += reader.dropped_events() - dropped;
+        dropped = reader.dropped_events();
+
+        match event {
+            AllocationEvent::Allocated {
+                addr,
+                size,
+                group_id,
+                ..
+            } => {
+                let size = size as u64;
+                live.insert(addr, (group_id, size));
+
+                let group = collector.groups.entry((connection, group_id)).or_default();
+                group.allocations += 1;
+                group.allocated_bytes += size;
+                group.live_bytes += size;
+            }
+            AllocationEvent::Deallocated { addr, .. } => {
+                // Deallocations are attributed to the group that made the allocation.  If we
+                // never saw the allocation, because it was dropped or made before the connection,
+                // there's nothing to attribute it to.
+                if let Some((group_id, size)) = live.remove(&addr) {
+                    let group = collector.groups.entry((connection, group_id)).or_default();
+                    group.deallocations += 1;
+                    group.freed_bytes += size;
+                    group.live_bytes = group.live_bytes.saturating_sub(size);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn render(collector: &Collector) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{} events from {} connections ({} active), {} events dropped",
+        collector.events,
+        collector.connections,
+        collector.active_connections,
+        collector.dropped_events
+    );
+    let _ = writeln!(
+        out,
+        "  {:>6} {:>8} {:>10} {:>10} {:>14} {:>14} {:>14}",
+        "conn", "group", "allocs", "deallocs", "allocated", "freed", "live"
+    );
+    for ((connection, id), group) in &collector.groups {
+        let _ = writeln!(
+            out,
+            "  {:>6} {:>8} {:>10} {:>10} {:>14} {:>14} {:>14}",
+            connection,
+            id,
+            group.allocations,
+            group.deallocations,
+            group.allocated_bytes,
+            group.freed_bytes,
+            group.live_bytes
+        );
+    }
+    out
+}
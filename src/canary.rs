@@ -0,0 +1,131 @@
+use std::{
+    alloc::Layout,
+    mem,
+    ptr::{self, NonNull},
+};
+
+use crate::token::AllocationGroupId;
+
+/// The number of canary bytes placed directly before, and directly after, the user region.
+const CANARY_LEN: usize = 16;
+
+/// The byte pattern written to each canary byte.
+const CANARY_BYTE: u8 = 0xFD;
+
+/// The number of bytes we need in front of the user region: the group ID of the allocation, plus
+/// the leading canary.
+const HEADER_LEN: usize = mem::size_of::<AllocationGroupId>() + CANARY_LEN;
+
+/// Layout of an allocation that has been padded with canaries.
+///
+/// The padded allocation is laid out like so:
+///
+/// ```text
+/// [ padding | group ID | leading canary | user region | trailing canary ]
+///                                       ^
+///                                       pointer handed back to the caller
+/// ```
+///
+/// The leading padding exists purely to keep the user region aligned to the alignment requested by
+/// the caller, and so the offset of the user region is always a multiple of that alignment.
+pub(crate) struct CanaryLayout {
+    outer: Layout,
+    offset: usize,
+    size: usize,
+}
+
+impl CanaryLayout {
+    /// Computes the padded layout for the given user layout.
+    ///
+    /// Returns `None` if the padded layout would overflow.
+    pub fn new(layout: Layout) -> Option<Self> {
+        let align = layout.align();
+        let offset = HEADER_LEN.checked_add(align - 1)? & !(align - 1);
+        let outer_size = offset.checked_add(layout.size())?.checked_add(CANARY_LEN)?;
+        let outer = Layout::from_size_align(outer_size, align).ok()?;
+
+        Some(Self {
+            outer,
+            offset,
+            size: layout.size(),
+        })
+    }
+
+    /// The layout to request from the wrapped allocator.
+    pub fn outer(&self) -> Layout {
+        self.outer
+    }
+
+    /// Writes the group ID and canaries into a freshly allocated outer region, returning the pointer
+    /// to the user region.
+    ///
+    /// # Safety
+    ///
+    /// `outer_ptr` must point to a live allocation made with the layout returned by [`outer`][Self::outer].
+    pub unsafe fn initialize(
+        &self,
+        outer_ptr: NonNull<u8>,
+        group_id: AllocationGroupId,
+    ) -> *mut u8 {
+        let user_ptr = outer_ptr.as_ptr().add(self.offset);
+        let leading = user_ptr.sub(CANARY_LEN);
+        let trailing = user_ptr.add(self.size);
+
+        ptr::write_unaligned(leading.sub(HEADER_LEN - CANARY_LEN) as *mut _, group_id);
+        ptr::write_bytes(leading, CANARY_BYTE, CANARY_LEN);
+        ptr::write_bytes(trailing, CANARY_BYTE, CANARY_LEN);
+
+        user_ptr
+    }
+
+    /// Gets the pointer to the outer region from the pointer to the user region.
+    ///
+    /// # Safety
+    ///
+    /// `user_ptr` must have been returned by [`initialize`][Self::initialize] for the same user layout.
+    pub unsafe fn outer_ptr(&self, user_ptr: *mut u8) -> *mut u8 {
+        user_ptr.sub(self.offset)
+    }
+
+    /// Checks that both canaries surrounding the user region are intact.
+    ///
+    /// If either canary has been overwritten, the group ID that the allocation was made under is
+    /// returned.
+    ///
+    /// # Safety
+    ///
+    /// `user_ptr` must have been returned by [`initialize`][Self::initialize] for the same user layout.
+    pub unsafe fn check(&self, user_ptr: *mut u8) -> Result<(), AllocationGroupId> {
+        let leading = user_ptr.sub(CANARY_LEN);
+        let trailing = user_ptr.add(self.size);
+
+        if is_intact(leading) && is_intact(trailing) {
+            Ok(())
+        } else {
+            Err(ptr::read_unaligned(
+                leading.sub(HEADER_LEN - CANARY_LEN) as *const AllocationGroupId
+            ))
+        }
+    }
+}
+
+unsafe fn is_intact(canary: *const u8) -> bool {
+    (0..CANARY_LEN).all(|i| *canary.add(i) == CANARY_BYTE)
+}
+
+/// Reports a corrupted allocation to standard error, and then aborts the process.
+///
+/// Formatting directly to standard error does not allocate, as it is unbuffered, so this is safe to
+/// call from within the allocator itself.
+pub(crate) fn report_corruption(addr: usize, size: usize, group_id: AllocationGroupId) -> ! {
+    use std::io::Write;
+
+    let _ = writeln!(
+        std::io::stderr(),
+        "tracking-allocator: canary overwritten for allocation at {:#x} (size={} group_id={:?})",
+        addr,
+        size,
+        group_id
+    );
+    std::process::abort()
+}
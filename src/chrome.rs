@@ -0,0 +1,108 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    stats::StatsTracker,
+    util::{group_name, write_json_string},
+    worker::WorkerHandle,
+};
+
+/// Periodically samples the live bytes of each allocation group into a Chrome trace.
+///
+/// Samples are written as counter events in the [Chrome trace event format][format], with one
+/// counter track per allocation group, which can be loaded in `chrome://tracing` or
+/// [Perfetto][perfetto] to view memory usage over time.  Statistics are read from a
+/// [`StatsTracker`], which must be installed as the global tracker.
+///
+/// Timestamps are in microseconds since the start time, which defaults to when the sampler was
+/// created.  When combining the output with another trace, such as one produced by
+/// `tracing-chrome`, the start time should be set to match the start time of that trace.
+///
+/// [format]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+/// [perfetto]: https://ui.perfetto.dev
+pub struct ChromeTraceSampler {
+    stats: Arc<StatsTracker>,
+    interval: Duration,
+    start: Instant,
+}
+
+impl ChromeTraceSampler {
+    /// Creates a new `ChromeTraceSampler` that reads from the given tracker.
+    ///
+    /// Samples are taken every 100 milliseconds by default.
+    pub fn new(stats: Arc<StatsTracker>) -> Self {
+        Self {
+            stats,
+            interval: Duration::from_millis(100),
+            start: Instant::now(),
+        }
+    }
+
+    /// Sets the interval at which samples are taken.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the start time that sample timestamps are relative to.
+    pub fn with_start_time(mut self, start: Instant) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Spawns a background thread that samples the tracker, writing the trace to `writer`.
+    ///
+    /// The trace is only complete once the returned handle has been finished or dropped.
+    /// Allocations made by the background thread are not tracked.
+    ///
+    /// # Errors
+    ///
+    /// If the trace header cannot be written, or the background thread cannot be spawned, an error
+    /// is returned.
+    pub fn spawn<W>(self, mut writer: W) -> io::Result<WorkerHandle>
+    where
+        W: Write + Send + 'static,
+    {
+        writer.write_all(b"[\n")?;
+
+        let pid = std::process::id();
+        let mut first = true;
+        WorkerHandle::spawn_periodic(
+            "tracking-allocator-chrome-trace",
+            self.interval,
+            move |done| {
+                let mut events = String::new();
+                let ts = self.start.elapsed().as_micros();
+                for stats in self.stats.snapshot() {
+                    if !first {
+                        events.push_str(",\n");
+                    }
+                    first = false;
+
+                    events.push_str("{\"name\":");
+                    let _ = write_json_string(&mut events, &group_name(&stats.group_id));
+                    let _ = write!(
+                    events,
+                    ",\"ph\":\"C\",\"ts\":{},\"pid\":{},\"tid\":0,\"args\":{{\"live_bytes\":{}}}}}",
+                    ts,
+                    pid,
+                    stats.live_bytes()
+                );
+                }
+
+                if done {
+                    events.push_str("\n]\n");
+                }
+                writer.write_all(events.as_bytes())?;
+                if done {
+                    writer.flush()?;
+                }
+                Ok(())
+            },
+        )
+    }
+}
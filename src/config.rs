@@ -0,0 +1,316 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    chrome::ChromeTraceSampler,
+    dhat::DhatTracker,
+    event_log::EventLogTracker,
+    stats::{render_report, StatsTracker},
+    token::AllocationGroupId,
+    worker::WorkerHandle,
+    AllocationRegistry, AllocationTracker,
+};
+
+const ENABLE_VAR: &str = "TRACKING_ALLOCATOR_ENABLE";
+const FORMAT_VAR: &str = "TRACKING_ALLOCATOR_FORMAT";
+const OUTPUT_VAR: &str = "TRACKING_ALLOCATOR_OUTPUT";
+const SAMPLE_RATE_VAR: &str = "TRACKING_ALLOCATOR_SAMPLE_RATE";
+const INTERVAL_VAR: &str = "TRACKING_ALLOCATOR_INTERVAL_MS";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Summary,
+    Prometheus,
+    Dhat,
+    EventLog,
+    Chrome,
+}
+
+impl Format {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "summary" => Some(Self::Summary),
+            "prometheus" => Some(Self::Prometheus),
+            "dhat" => Some(Self::Dhat),
+            "event-log" => Some(Self::EventLog),
+            "chrome" => Some(Self::Chrome),
+            _ => None,
+        }
+    }
+
+    fn default_output(self) -> &'static str {
+        match self {
+            Self::Summary | Self::Prometheus => "stderr",
+            Self::Dhat => "dhat-heap.json",
+            Self::EventLog => "allocations.talog",
+            Self::Chrome => "chrome-trace.json",
+        }
+    }
+}
+
+struct Config {
+    format: Format,
+    output: String,
+    sample_rate: f64,
+    interval: Duration,
+}
+
+impl AllocationRegistry {
+    /// Configures tracking from environment variables, installing one of the built-in trackers as
+    /// the global tracker and enabling tracking.
+    ///
+    /// This allows any binary using [`Allocator`][crate::Allocator] to be profiled without being
+    /// recompiled.  Nothing is done unless `TRACKING_ALLOCATOR_ENABLE` is set to `1`, `true`, `yes`,
+    /// or `on`, in which case the following variables are read:
+    ///
+    /// - `TRACKING_ALLOCATOR_FORMAT`: what to produce, which is one of `summary` (the default), a
+    ///   human-readable report of per-group statistics and the groups with the most live bytes;
+    ///   `prometheus`, per-group statistics in the Prometheus text exposition format; `dhat`, a
+    ///   DHAT heap profile; `event-log`, a log of every allocation event, as written by
+    ///   [`EventLogTracker`]; or `chrome`, a Chrome trace of per-group live bytes over time
+    /// - `TRACKING_ALLOCATOR_OUTPUT`: where to write the output, which is either a file path, or
+    ///   `stderr` or `stdout`.  Defaults to `stderr` for `summary` and `prometheus`,
+    ///   `dhat-heap.json` for `dhat`, `allocations.talog` for `event-log`, and `chrome-trace.json`
+    ///   for `chrome`
+    /// - `TRACKING_ALLOCATOR_SAMPLE_RATE`: the fraction of allocations to track, between `0` and
+    ///   `1`, defaulting to `1`.  Allocations are sampled by address, so the deallocation of a
+    ///   sampled allocation is always tracked as well, and the statistics that are produced only
+    ///   reflect the sampled allocations
+    /// - `TRACKING_ALLOCATOR_INTERVAL_MS`: how often, in milliseconds, the `chrome` format samples
+    ///   per-group live bytes, defaulting to `100`
+    ///
+    /// If tracking was configured, a handle is returned which writes out the output, flushing it,
+    /// once it is finished or dropped, so it should be held on to until the program is done, such
+    /// as by binding it to a variable at the start of `main`.  Output for the `event-log` and
+    /// `chrome` formats is also written incrementally while the program runs.
+    ///
+    /// # Errors
+    ///
+    /// If any of the variables has an invalid value, the output cannot be opened, a global tracker
+    /// has already been set, or a background thread cannot be spawned, an error is returned.
+    pub fn configure_from_env() -> io::Result<Option<WorkerHandle>> {
+        let config = match Config::from_env()? {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        let output = open_output(&config.output)?;
+        let handle = match config.format {
+            Format::Summary | Format::Prometheus => {
+                let stats = Arc::new(StatsTracker::new());
+                install(Arc::clone(&stats), config.sample_rate)?;
+
+                let format = config.format;
+                spawn_on_finish(output, move |writer| {
+                    let report = match format {
+                        Format::Prometheus => stats.render_prometheus(),
+                        _ => render_report(&stats.snapshot()),
+                    };
+                    writer.write_all(report.as_bytes())
+                })?
+            }
+            Format::Dhat => {
+                let dhat = Arc::new(DhatTracker::new());
+                install(Arc::clone(&dhat), config.sample_rate)?;
+                spawn_on_finish(output, move |writer| dhat.write_json(writer))?
+            }
+            Format::EventLog => {
+                let (tracker, handle) = EventLogTracker::new(output)?;
+                install(tracker, config.sample_rate)?;
+                handle
+            }
+            Format::Chrome => {
+                let stats = Arc::new(StatsTracker::new());
+                install(Arc::clone(&stats), config.sample_rate)?;
+                ChromeTraceSampler::new(stats)
+                    .with_interval(config.interval)
+                    .spawn(output)?
+            }
+        };
+
+        AllocationRegistry::enable_tracking();
+        Ok(Some(handle))
+    }
+}
+
+impl Config {
+    fn from_env() -> io::Result<Option<Self>> {
+        let enabled = match var(ENABLE_VAR)? {
+            Some(value) => parse_bool(ENABLE_VAR, &value)?,
+            None => false,
+        };
+        if !enabled {
+            return Ok(None);
+        }
+
+        let format = match var(FORMAT_VAR)? {
+            Some(value) => Format::parse(&value).ok_or_else(|| {
+                invalid(
+                    FORMAT_VAR,
+                    &value,
+                    "expected one of summary, prometheus, dhat, event-log or chrome",
+                )
+            })?,
+            None => Format::Summary,
+        };
+
+        let output = var(OUTPUT_VAR)?.unwrap_or_else(|| format.default_output().to_string());
+
+        let sample_rate = match var(SAMPLE_RATE_VAR)? {
+            Some(value) => value
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| (0.0..=1.0).contains(rate))
+                .ok_or_else(|| {
+                    invalid(SAMPLE_RATE_VAR, &value, "expected a number between 0 and 1")
+                })?,
+            None => 1.0,
+        };
+
+        let interval = match var(INTERVAL_VAR)? {
+            Some(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|interval| *interval > 0)
+                .map(Duration::from_millis)
+                .ok_or_else(|| invalid(INTERVAL_VAR, &value, "expected a positive integer"))?,
+            None => Duration::from_millis(100),
+        };
+
+        Ok(Some(Self {
+            format,
+            output,
+            sample_rate,
+            interval,
+        }))
+    }
+}
+
+/// Gets the value of an environment variable, treating an empty value as unset.
+fn var(name: &str) -> io::Result<Option<String>> {
+    match env::var(name) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not valid unicode", name),
+        )),
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> io::Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(invalid(name, value, "expected a boolean")),
+    }
+}
+
+fn invalid(name: &str, value: &str, expected: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid value '{}' for {}: {}", value, name, expected),
+    )
+}
+
+fn open_output(output: &str) -> io::Result<Box<dyn Write + Send>> {
+    match output {
+        "stderr" => Ok(Box::new(io::stderr())),
+        "stdout" => Ok(Box::new(io::stdout())),
+        path => Ok(Box::new(BufWriter::new(File::create(path)?))),
+    }
+}
+
+fn install<T>(tracker: T, sample_rate: f64) -> io::Result<()>
+where
+    T: AllocationTracker + Send + Sync + 'static,
+{
+    let result = if sample_rate < 1.0 {
+        AllocationRegistry::set_global_tracker(SampledTracker::new(tracker, sample_rate))
+    } else {
+        AllocationRegistry::set_global_tracker(tracker)
+    };
+    result.map_err(|e| io::Error::new(io::ErrorKind::AlreadyExists, e))
+}
+
+/// Spawns a background thread that waits until its handle is finished or dropped, and then writes
+/// the output.
+fn spawn_on_finish<F>(mut output: Box<dyn Write + Send>, f: F) -> io::Result<WorkerHandle>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+{
+    WorkerHandle::spawn("tracking-allocator-env", move |shutdown| {
+        // The sender is never used, so this only returns once the handle is finished or dropped.
+        let _ = shutdown.recv();
+        f(&mut output)?;
+        output.flush()
+    })
+}
+
+/// Forwards a fraction of allocations, chosen by address, to another tracker.
+///
+/// Choosing by address, rather than at random, means the deallocation of a sampled allocation is
+/// always forwarded too, without having to remember which allocations were sampled.
+struct SampledTracker<T> {
+    inner: T,
+    threshold: u64,
+}
+
+impl<T> SampledTracker<T> {
+    fn new(inner: T, sample_rate: f64) -> Self {
+        Self {
+            inner,
+            threshold: (sample_rate * u64::MAX as f64) as u64,
+        }
+    }
+
+    fn sampled(&self, addr: usize) -> bool {
+        // The finalizer of SplitMix64, which spreads the bits of the address around, so that the
+        // alignment of allocations doesn't skew which ones get sampled.
+        let mut x = addr as u64;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^= x >> 31;
+        x < self.threshold
+    }
+}
+
+impl<T: AllocationTracker> AllocationTracker for SampledTracker<T> {
+    fn allocated(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        if self.sampled(addr) {
+            self.inner.allocated(addr, size, group_id);
+        }
+    }
+
+    fn deallocated(&self, addr: usize, current_group_id: AllocationGroupId) {
+        if self.sampled(addr) {
+            self.inner.deallocated(addr, current_group_id);
+        }
+    }
+
+    fn corrupted(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        self.inner.corrupted(addr, size, group_id);
+    }
+
+    fn registered(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        self.inner.registered(group_id, tags);
+    }
+
+    fn entered(&self, group_id: AllocationGroupId) {
+        self.inner.entered(group_id);
+    }
+
+    fn exited(&self, group_id: AllocationGroupId) {
+        self.inner.exited(group_id);
+    }
+
+    fn released(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        self.inner.released(group_id, tags);
+    }
+}
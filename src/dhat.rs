@@ -0,0 +1,335 @@
+use std::{collections::HashMap, fmt::Write as _, io, mem, sync::Mutex, time::Instant};
+
+use crate::{
+    token::AllocationGroupId,
+    util::{group_name, write_json_string},
+    AllocationRegistry, AllocationTracker,
+};
+
+/// An [`AllocationTracker`] that produces heap profiles in [DHAT][dhat]'s JSON format.
+///
+/// DHAT organizes its data by "program points", which are normally the stack traces at which
+/// allocations occurred.  As stacks are not captured, each allocation group is mapped to its own
+/// program point instead, such that the DHAT viewer shows, per allocation group, the total bytes
+/// and blocks allocated, the bytes and blocks live at the global peak and at exit, and the average
+/// lifetime of blocks.  Access counts are not tracked.
+///
+/// Once an allocation group has been released, and every block allocated within it has been
+/// freed, its program point is folded into a single program point for released allocation groups,
+/// so that the profile stays bounded by the number of allocation groups registered at once.
+///
+/// Unlike most trackers, `DhatTracker` allocates in order to track allocations.  This is safe as
+/// allocations made from within a tracker are not themselves tracked, but it does mean that every
+/// tracked allocation incurs some amount of overhead, and takes a lock.
+///
+/// Once the program has run long enough, the profile can be written with
+/// [`write_json`][DhatTracker::write_json], and then loaded into the [DHAT viewer][viewer].
+/// Blocks still alive at that point are treated as being alive "at exit".
+///
+/// [dhat]: https://valgrind.org/docs/manual/dh-manual.html
+/// [viewer]: https://nnethercote.github.io/dh_view/dh_view.html
+pub struct DhatTracker {
+    start: Instant,
+    state: Mutex<DhatState>,
+}
+
+#[derive(Default)]
+struct DhatState {
+    // Live blocks, keyed by address.
+    blocks: HashMap<usize, Block>,
+
+    // Program points, and a lookup from allocation group to the index of its program point.  The
+    // program points of released groups are folded into `retired`, and their indexes reused.
+    pps: Vec<ProgramPoint>,
+    pp_indexes: HashMap<AllocationGroupId, usize>,
+    free_pps: Vec<usize>,
+    retired: ProgramPoint,
+
+    curr_bytes: u64,
+    curr_blocks: u64,
+    max_bytes: u64,
+    max_blocks: u64,
+    tgmax: u64,
+}
+
+struct Block {
+    pp: usize,
+    size: u64,
+    allocated_at: u64,
+}
+
+#[derive(Default)]
+struct ProgramPoint {
+    // The allocation group of the program point, or `None` if the program point is unused.
+    group_id: Option<AllocationGroupId>,
+    released: bool,
+    total_bytes: u64,
+    total_blocks: u64,
+    total_lifetimes: u64,
+    curr_bytes: u64,
+    curr_blocks: u64,
+    max_bytes: u64,
+    max_blocks: u64,
+    at_tgmax_bytes: u64,
+    at_tgmax_blocks: u64,
+}
+
+impl DhatTracker {
+    /// Creates a new `DhatTracker`.
+    ///
+    /// Times in the profile are relative to when the tracker was created.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            state: Mutex::new(DhatState::default()),
+        }
+    }
+
+    /// Writes the current profile, in DHAT's JSON format, to the given writer.
+    ///
+    /// # Errors
+    ///
+    /// If an error occurs while writing to `writer`, it is returned.
+    pub fn write_json<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        // We hold the same lock that the tracker acquires, so we can't let our own allocations be
+        // tracked, otherwise we'd deadlock.
+        AllocationRegistry::untracked(|| {
+            let json = self.to_json();
+            writer.write_all(json.as_bytes())
+        })
+    }
+
+    fn elapsed(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn to_json(&self) -> String {
+        let te = self.elapsed();
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Blocks that are still alive haven't had their lifetimes accounted for yet.
+        let mut live_lifetimes = vec![0u64; state.pps.len()];
+        for block in state.blocks.values() {
+            live_lifetimes[block.pp] += te.saturating_sub(block.allocated_at);
+        }
+
+        let pps = state
+            .pps
+            .iter()
+            .zip(live_lifetimes)
+            .filter_map(|(pp, live_lifetime)| {
+                let name = group_name(pp.group_id.as_ref()?);
+                Some((name, pp, live_lifetime))
+            })
+            .chain(
+                Some(("released allocation groups".to_string(), &state.retired, 0))
+                    .filter(|(_, pp, _)| pp.total_blocks > 0),
+            )
+            .collect::<Vec<_>>();
+        let at_peak = state.at_peak();
+
+        let cmd = std::env::args().collect::<Vec<_>>().join(" ");
+
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\"dhatFileVersion\":2,\"mode\":\"rust-heap\",\"verb\":\"Allocated\",\
+             \"bklt\":true,\"bkacc\":false,\"tu\":\"µs\",\"Mtu\":\"s\",\"tuth\":10,\"cmd\":"
+        );
+        let _ = write_json_string(&mut json, &cmd);
+        let _ = write!(
+            json,
+            ",\"pid\":{},\"tg\":{},\"te\":{},\"pps\":[",
+            std::process::id(),
+            state.tgmax,
+            te
+        );
+        for (i, (_, pp, live_lifetime)) in pps.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let (at_tgmax_bytes, at_tgmax_blocks) = pp.at_tgmax(at_peak);
+            let _ = write!(
+                json,
+                "{{\"tb\":{},\"tbk\":{},\"tl\":{},\"mb\":{},\"mbk\":{},\"gb\":{},\"gbk\":{},\
+                 \"eb\":{},\"ebk\":{},\"fs\":[{}]}}",
+                pp.total_bytes,
+                pp.total_blocks,
+                pp.total_lifetimes + live_lifetime,
+                pp.max_bytes,
+                pp.max_blocks,
+                at_tgmax_bytes,
+                at_tgmax_blocks,
+                pp.curr_bytes,
+                pp.curr_blocks,
+                i + 1
+            );
+        }
+        json.push_str("],\"ftbl\":[\"[root]\"");
+        for (name, _, _) in &pps {
+            json.push(',');
+            let _ = write_json_string(&mut json, name);
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+impl Default for DhatTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AllocationTracker for DhatTracker {
+    fn allocated(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        let now = self.elapsed();
+        let size = size as u64;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let state = &mut *state;
+
+        let pp_index = match state.pp_indexes.get(&group_id) {
+            Some(pp_index) => *pp_index,
+            None => {
+                let pp = ProgramPoint::new(group_id);
+                let pp_index = match state.free_pps.pop() {
+                    Some(pp_index) => {
+                        state.pps[pp_index] = pp;
+                        pp_index
+                    }
+                    None => {
+                        state.pps.push(pp);
+                        state.pps.len() - 1
+                    }
+                };
+                state.pp_indexes.insert(group_id, pp_index);
+                pp_index
+            }
+        };
+
+        let pp = &mut state.pps[pp_index];
+        pp.total_bytes += size;
+        pp.total_blocks += 1;
+        pp.curr_bytes += size;
+        pp.curr_blocks += 1;
+        if pp.curr_bytes >= pp.max_bytes {
+            pp.max_bytes = pp.curr_bytes;
+            pp.max_blocks = pp.curr_blocks;
+        }
+
+        // What each program point had live at the global peak is only recorded once usage drops
+        // from the peak, rather than every time a new peak is reached.
+        state.curr_bytes += size;
+        state.curr_blocks += 1;
+        if state.curr_bytes >= state.max_bytes {
+            state.max_bytes = state.curr_bytes;
+            state.max_blocks = state.curr_blocks;
+            state.tgmax = now;
+        }
+
+        state.blocks.insert(
+            addr,
+            Block {
+                pp: pp_index,
+                size,
+                allocated_at: now,
+            },
+        );
+    }
+
+    fn deallocated(&self, addr: usize, _current_group_id: AllocationGroupId) {
+        let now = self.elapsed();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Allocations made before the tracker was installed, or while tracking was disabled, were
+        // never seen, so there's nothing to account for.
+        if let Some(block) = state.blocks.remove(&addr) {
+            if state.at_peak() {
+                state.record_peak();
+            }
+            state.curr_bytes -= block.size;
+            state.curr_blocks -= 1;
+
+            let pp = &mut state.pps[block.pp];
+            pp.curr_bytes -= block.size;
+            pp.curr_blocks -= 1;
+            pp.total_lifetimes += now.saturating_sub(block.allocated_at);
+            if pp.released && pp.curr_blocks == 0 {
+                state.retire(block.pp);
+            }
+        }
+    }
+
+    fn released(&self, group_id: AllocationGroupId, _tags: &[(String, String)]) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Blocks allocated within the group may outlive it, in which case the program point is
+        // retired once the last of them is freed.
+        if let Some(pp_index) = state.pp_indexes.remove(&group_id) {
+            let pp = &mut state.pps[pp_index];
+            pp.released = true;
+            if pp.curr_blocks == 0 {
+                state.retire(pp_index);
+            }
+        }
+    }
+}
+
+impl DhatState {
+    /// Whether or not usage is currently at the global peak.
+    fn at_peak(&self) -> bool {
+        self.curr_bytes == self.max_bytes
+    }
+
+    /// Records what each program point has live, as usage is about to drop from the global peak.
+    fn record_peak(&mut self) {
+        for pp in &mut self.pps {
+            pp.at_tgmax_bytes = pp.curr_bytes;
+            pp.at_tgmax_blocks = pp.curr_blocks;
+        }
+
+        // Program points are only retired once they have nothing live.
+        self.retired.at_tgmax_bytes = 0;
+        self.retired.at_tgmax_blocks = 0;
+    }
+
+    /// Folds the program point at the given index, which must have nothing live, into the program
+    /// point for released groups, and frees up its index.
+    fn retire(&mut self, pp_index: usize) {
+        let at_peak = self.at_peak();
+        let pp = mem::take(&mut self.pps[pp_index]);
+        let (at_tgmax_bytes, at_tgmax_blocks) = pp.at_tgmax(at_peak);
+
+        let retired = &mut self.retired;
+        retired.total_bytes += pp.total_bytes;
+        retired.total_blocks += pp.total_blocks;
+        retired.total_lifetimes += pp.total_lifetimes;
+        retired.max_bytes = retired.max_bytes.max(pp.max_bytes);
+        retired.max_blocks = retired.max_blocks.max(pp.max_blocks);
+        retired.at_tgmax_bytes += at_tgmax_bytes;
+        retired.at_tgmax_blocks += at_tgmax_blocks;
+
+        self.free_pps.push(pp_index);
+    }
+}
+
+impl ProgramPoint {
+    fn new(group_id: AllocationGroupId) -> Self {
+        Self {
+            group_id: Some(group_id),
+            ..Default::default()
+        }
+    }
+
+    /// The bytes and blocks that were live at the global peak.
+    ///
+    /// While usage is at the global peak, that's what is live now, as what was live at the peak
+    /// is only recorded once usage drops from it.
+    fn at_tgmax(&self, at_peak: bool) -> (u64, u64) {
+        if at_peak {
+            (self.curr_bytes, self.curr_blocks)
+        } else {
+            (self.at_tgmax_bytes, self.at_tgmax_blocks)
+        }
+    }
+}
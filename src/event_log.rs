@@ -0,0 +1,508 @@
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    io::{self, BufReader, Read, Write},
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender, TryRecvError, TrySendError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    token::AllocationGroupId, util::write_varint, worker::WorkerHandle, AllocationTracker,
+};
+
+const MAGIC: &[u8; 5] = b"TALOG";
+const VERSION: u8 = 2;
+
+const KIND_ALLOCATED: u8 = 0;
+const KIND_DEALLOCATED: u8 = 1;
+
+/// Size at which a buffer of encoded events is handed off to the background thread.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Number of full buffers that can be waiting on the background thread.
+const BUFFER_QUEUE_LEN: usize = 16;
+
+static NEXT_THREAD_INDEX: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD_INDEX: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Gets the index of the current thread, assigning one if this is the first time it was requested.
+pub(crate) fn thread_index() -> u64 {
+    THREAD_INDEX
+        .try_with(|index| {
+            if index.get() == 0 {
+                index.set(NEXT_THREAD_INDEX.fetch_add(1, Ordering::Relaxed));
+            }
+            index.get()
+        })
+        .unwrap_or(0)
+}
+
+/// A single recorded allocation event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AllocationEvent {
+    /// An allocation occurred.
+    Allocated {
+        /// Nanoseconds since recording started.
+        timestamp: u64,
+
+        /// Index of the thread that made the allocation.
+        thread: u64,
+
+        /// Address of the allocation.
+        addr: usize,
+
+        /// Size of the allocation, in bytes.
+        size: usize,
+
+        /// The allocation group that was active when the allocation was made.
+        group_id: AllocationGroupId,
+    },
+
+    /// A deallocation occurred.
+    Deallocated {
+        /// Nanoseconds since recording started.
+        timestamp: u64,
+
+        /// Index of the thread that made the deallocation.
+        thread: u64,
+
+        /// Address of the allocation being deallocated.
+        addr: usize,
+
+        /// The allocation group that was active when the deallocation was made.
+        current_group_id: AllocationGroupId,
+    },
+}
+
+impl AllocationEvent {
+    /// Nanoseconds since recording started.
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Self::Allocated { timestamp, .. } | Self::Deallocated { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// Index of the thread that the event occurred on.
+    pub fn thread(&self) -> u64 {
+        match self {
+            Self::Allocated { thread, .. } | Self::Deallocated { thread, .. } => *thread,
+        }
+    }
+
+    /// Address of the allocation.
+    pub fn addr(&self) -> usize {
+        match self {
+            Self::Allocated { addr, .. } | Self::Deallocated { addr, .. } => *addr,
+        }
+    }
+}
+
+/// Delta encoder for allocation events.
+#[derive(Default)]
+pub(crate) struct EventEncoder {
+    timestamp: u64,
+    thread: u64,
+    addr: u64,
+    group_id: u64,
+    size: u64,
+}
+
+impl EventEncoder {
+    pub fn encode(&mut self, buf: &mut Vec<u8>, event: &AllocationEvent) {
+        let (kind, timestamp, thread, addr, group_id, size) = match event {
+            AllocationEvent::Allocated {
+                timestamp,
+                thread,
+                addr,
+                size,
+                group_id,
+            } => (
+                KIND_ALLOCATED,
+                *timestamp,
+                *thread,
+                *addr,
+                *group_id,
+                Some(*size),
+            ),
+            AllocationEvent::Deallocated {
+                timestamp,
+                thread,
+                addr,
+                current_group_id,
+            } => (
+                KIND_DEALLOCATED,
+                *timestamp,
+                *thread,
+                *addr,
+                *current_group_id,
+                None,
+            ),
+        };
+
+        buf.push(kind);
+        write_varint(buf, timestamp.saturating_sub(self.timestamp));
+        write_varint(buf, zigzag_delta(&mut self.thread, thread));
+        write_varint(buf, zigzag_delta(&mut self.addr, addr as u64));
+        write_varint(
+            buf,
+            zigzag_delta(&mut self.group_id, group_id.as_usize() as u64),
+        );
+        write_varint(buf, u64::from(group_id.generation()));
+        if let Some(size) = size {
+            write_varint(buf, zigzag_delta(&mut self.size, size as u64));
+        }
+        self.timestamp = self.timestamp.max(timestamp);
+    }
+}
+
+/// Delta decoder for allocation events.
+#[derive(Default)]
+pub(crate) struct EventDecoder {
+    timestamp: u64,
+    thread: u64,
+    addr: u64,
+    group_id: u64,
+    size: u64,
+}
+
+impl EventDecoder {
+    /// Decodes the next event from `reader`.
+    ///
+    /// If `reader` is at the end of its input, `None` is returned.
+    pub fn decode<R: Read>(&mut self, reader: &mut R) -> io::Result<Option<AllocationEvent>> {
+        let mut kind = [0u8; 1];
+        if reader.read(&mut kind)? == 0 {
+            return Ok(None);
+        }
+
+        self.timestamp = self.timestamp.wrapping_add(read_varint(reader)?);
+        let thread = apply_zigzag_delta(&mut self.thread, read_varint(reader)?);
+        let addr = apply_zigzag_delta(&mut self.addr, read_varint(reader)?) as usize;
+        let group_index = apply_zigzag_delta(&mut self.group_id, read_varint(reader)?) as usize;
+        let generation = u32::try_from(read_varint(reader)?).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "allocation group generation is out of range",
+            )
+        })?;
+        let group_id = AllocationGroupId::from_parts(group_index, generation);
+        let timestamp = self.timestamp;
+
+        match kind[0] {
+            KIND_ALLOCATED => {
+                let size = apply_zigzag_delta(&mut self.size, read_varint(reader)?) as usize;
+                Ok(Some(AllocationEvent::Allocated {
+                    timestamp,
+                    thread,
+                    addr,
+                    size,
+                    group_id,
+                }))
+            }
+            KIND_DEALLOCATED => Ok(Some(AllocationEvent::Deallocated {
+                timestamp,
+                thread,
+                addr,
+                current_group_id: group_id,
+            })),
+            kind => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown event kind {}", kind),
+            )),
+        }
+    }
+}
+
+fn zigzag_delta(previous: &mut u64, value: u64) -> u64 {
+    let delta = value.wrapping_sub(*previous) as i64;
+    *previous = value;
+    ((delta << 1) ^ (delta >> 63)) as u64
+}
+
+fn apply_zigzag_delta(previous: &mut u64, encoded: u64) -> u64 {
+    let delta = ((encoded >> 1) as i64) ^ -((encoded & 1) as i64);
+    *previous = previous.wrapping_add(delta as u64);
+    *previous
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    read_varint_or_eof(reader)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+/// Reads an unsigned LEB128 varint, returning `None` if `reader` is at the end of its input.
+///
+/// Running out of input partway through the varint is still an error.
+pub(crate) fn read_varint_or_eof<R: Read>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte)? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err(io::ErrorKind::UnexpectedEof.into())
+            };
+        }
+        if shift >= 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "varint is too long",
+            ));
+        }
+        value |= u64::from(byte[0] & 0x7F) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+        shift += 7;
+    }
+}
+
+/// Writes a header made up of the given magic bytes followed by the given version byte.
+pub(crate) fn write_header<W: Write>(
+    writer: &mut W,
+    magic: &[u8; 5],
+    version: u8,
+) -> io::Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&[version])
+}
+
+/// Reads and validates a header written by [`write_header`].
+///
+/// `format` names the format in the errors returned, as in `"event log"`.
+pub(crate) fn read_header<R: Read>(
+    reader: &mut R,
+    magic: &[u8; 5],
+    version: u8,
+    format: &str,
+) -> io::Result<()> {
+    let mut header = [0u8; 6];
+    reader.read_exact(&mut header)?;
+    if &header[..5] != magic {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not a tracking-allocator {}", format),
+        ));
+    }
+    if header[5] != version {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported {} version {}", format, header[5]),
+        ));
+    }
+    Ok(())
+}
+
+/// An [`AllocationTracker`] that records every allocation event to a compact binary log.
+///
+/// Events are encoded into an in-memory buffer, and full buffers are handed off to a background
+/// thread, which writes them to the underlying writer.  If the background thread falls behind,
+/// events keep being buffered in memory until it catches up, so that no events are lost and the
+/// tracker never blocks.  Logs can be read back with [`EventLogReader`].
+///
+/// Like [`StatsTracker`][crate::StatsTracker], the tracker takes a lock for every event.
+///
+/// ## Format
+///
+/// A log starts with a header, made up of the magic bytes `TALOG` followed by a single version byte,
+/// which is currently `2`.  The header is followed by zero or more records, one per event, until
+/// the end of the log.
+///
+/// Each record starts with a kind byte, `0` for an allocation and `1` for a deallocation, followed
+/// by a series of LEB128 varints:
+///
+/// - the timestamp, in nanoseconds, as a delta from the timestamp of the previous record
+/// - the thread index, as a zigzag-encoded delta from the thread index of the previous record
+/// - the address, as a zigzag-encoded delta from the address of the previous record
+/// - the index of the allocation group, as a zigzag-encoded delta from the index of the previous
+///   record
+/// - the generation of the allocation group
+/// - for allocations only, the size, as a zigzag-encoded delta from the size of the previous
+///   allocation
+///
+/// All "previous" values start out as zero.  Timestamps are relative to when recording started,
+/// and thread indexes are small integers assigned to threads in the order they first allocate, and
+/// so are not the same as operating system thread IDs.
+pub struct EventLogTracker {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    start: Instant,
+    state: Mutex<WriterState>,
+}
+
+struct WriterState {
+    encoder: EventEncoder,
+    buf: Vec<u8>,
+    buffers: SyncSender<Vec<u8>>,
+}
+
+impl EventLogTracker {
+    /// Creates a new `EventLogTracker` that writes to `writer`.
+    ///
+    /// A handle to the background thread is returned alongside the tracker.  Once finished, or
+    /// dropped, the handle writes out any events that are still buffered and flushes the writer.
+    /// Events which occur after that are discarded.
+    ///
+    /// # Errors
+    ///
+    /// If the header cannot be written, or the background thread cannot be spawned, an error is
+    /// returned.
+    pub fn new<W>(mut writer: W) -> io::Result<(EventLogTracker, WorkerHandle)>
+    where
+        W: Write + Send + 'static,
+    {
+        write_header(&mut writer, MAGIC, VERSION)?;
+
+        let (buffers, buffers_rx) = mpsc::sync_channel(BUFFER_QUEUE_LEN);
+        let shared = Arc::new(Shared {
+            start: Instant::now(),
+            state: Mutex::new(WriterState {
+                encoder: EventEncoder::default(),
+                buf: Vec::with_capacity(BUFFER_SIZE),
+                buffers,
+            }),
+        });
+
+        let worker_shared = Arc::clone(&shared);
+        let handle = WorkerHandle::spawn("tracking-allocator-event-log", move |shutdown| {
+            loop {
+                match buffers_rx.recv_timeout(Duration::from_millis(50)) {
+                    Ok(buf) => writer.write_all(&buf)?,
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                match shutdown.try_recv() {
+                    Err(TryRecvError::Empty) => {}
+                    Ok(()) | Err(TryRecvError::Disconnected) => break,
+                }
+            }
+
+            // Anything that was handed off has to be written before whatever is still buffered,
+            // as each buffer is delta-encoded relative to the ones before it.  We drain the queue
+            // before taking the lock, so that we never wait on the lock while holding up the
+            // queue, and then once more, for anything handed off in the meantime.
+            while let Ok(buf) = buffers_rx.try_recv() {
+                writer.write_all(&buf)?;
+            }
+            let mut state = worker_shared.lock();
+            while let Ok(buf) = buffers_rx.try_recv() {
+                writer.write_all(&buf)?;
+            }
+            writer.write_all(&state.buf)?;
+            state.buf.clear();
+            writer.flush()
+        })?;
+
+        Ok((EventLogTracker { shared }, handle))
+    }
+
+    fn record<F>(&self, event: F)
+    where
+        F: FnOnce(u64) -> AllocationEvent,
+    {
+        let mut state = self.shared.lock();
+        let state = &mut *state;
+
+        // Timestamps are delta-encoded, so they have to be taken under the lock for events to be
+        // encoded in the order they occurred in.
+        let event = event(self.shared.start.elapsed().as_nanos() as u64);
+        state.encoder.encode(&mut state.buf, &event);
+        if state.buf.len() >= BUFFER_SIZE {
+            // We hand off while holding the lock, so that buffers are always queued in the order
+            // they were encoded in, which means we can't wait for room in the queue.  If it's full,
+            // we keep the buffer and try again on the next event.  If the background thread has
+            // exited, the events are discarded.
+            let buf = mem::take(&mut state.buf);
+            match state.buffers.try_send(buf) {
+                Ok(()) => state.buf = Vec::with_capacity(BUFFER_SIZE),
+                Err(TrySendError::Full(buf)) => state.buf = buf,
+                Err(TrySendError::Disconnected(_)) => {}
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, WriterState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl AllocationTracker for EventLogTracker {
+    fn allocated(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        let thread = thread_index();
+        self.record(|timestamp| AllocationEvent::Allocated {
+            timestamp,
+            thread,
+            addr,
+            size,
+            group_id,
+        });
+    }
+
+    fn deallocated(&self, addr: usize, current_group_id: AllocationGroupId) {
+        let thread = thread_index();
+        self.record(|timestamp| AllocationEvent::Deallocated {
+            timestamp,
+            thread,
+            addr,
+            current_group_id,
+        });
+    }
+}
+
+/// Reads allocation events back from a log written by [`EventLogTracker`].
+pub struct EventLogReader<R> {
+    reader: BufReader<R>,
+    decoder: EventDecoder,
+    failed: bool,
+}
+
+impl<R: Read> EventLogReader<R> {
+    /// Creates a new `EventLogReader`, reading and validating the header of the log.
+    ///
+    /// # Errors
+    ///
+    /// If the header cannot be read, or is not a supported event log header, an error is returned.
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        read_header(&mut reader, MAGIC, VERSION, "event log")?;
+
+        Ok(Self {
+            reader,
+            decoder: EventDecoder::default(),
+            failed: false,
+        })
+    }
+}
+
+impl<R: Read> Iterator for EventLogReader<R> {
+    type Item = io::Result<AllocationEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        // Once we've hit an error, we can't know where the next record starts, so we stop.
+        match self.decoder.decode(&mut self.reader) {
+            Ok(event) => event.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
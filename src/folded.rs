@@ -0,0 +1,108 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use crate::{token::AllocationGroupId, util::group_name};
+
+/// Builds memory flamegraph input in the folded-stack format.
+///
+/// The folded-stack format, as used by `flamegraph.pl` and `inferno`, is one line per unique stack,
+/// made up of the frames of the stack from the outermost frame to the innermost frame separated by
+/// semicolons, followed by a space and the weight of the stack.  Stacks are weighted by bytes.
+///
+/// Whether the flamegraph shows allocated bytes or live bytes depends on how samples are fed in:
+/// to show allocated bytes, add a sample for each allocation.  To show live bytes, additionally
+/// remove the sample when the allocation is deallocated, with
+/// [`remove_sample`][FoldedStacks::remove_sample].
+///
+/// Just like [`PprofProfile`][crate::PprofProfile], stacks are provided as a list of function
+/// names, ordered from the innermost frame to the outermost frame, and capturing them is left to
+/// the caller.  Optionally, the allocation group of each sample can be included as the root frame
+/// of its stack, which splits the flamegraph by allocation group.
+#[derive(Default)]
+pub struct FoldedStacks {
+    group_frames: bool,
+    stacks: BTreeMap<String, u64>,
+}
+
+impl FoldedStacks {
+    /// Creates an empty `FoldedStacks`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether or not the allocation group of each sample is included as its root frame.
+    ///
+    /// Defaults to `false`.  This only applies to samples added after it has been set.
+    pub fn with_group_frames(mut self, group_frames: bool) -> Self {
+        self.group_frames = group_frames;
+        self
+    }
+
+    /// Adds `bytes` to the weight of the given stack.
+    ///
+    /// `stack` must be ordered from the innermost frame to the outermost frame.
+    pub fn add_sample<S>(&mut self, group_id: &AllocationGroupId, bytes: usize, stack: &[S])
+    where
+        S: AsRef<str>,
+    {
+        let key = self.fold(group_id, stack);
+        *self.stacks.entry(key).or_default() += bytes as u64;
+    }
+
+    /// Removes `bytes` from the weight of the given stack.
+    ///
+    /// Once a stack has no weight remaining, it is no longer written out.
+    pub fn remove_sample<S>(&mut self, group_id: &AllocationGroupId, bytes: usize, stack: &[S])
+    where
+        S: AsRef<str>,
+    {
+        let key = self.fold(group_id, stack);
+        if let Some(weight) = self.stacks.get_mut(&key) {
+            *weight = weight.saturating_sub(bytes as u64);
+            if *weight == 0 {
+                self.stacks.remove(&key);
+            }
+        }
+    }
+
+    /// Writes the folded stacks to the given writer.
+    ///
+    /// # Errors
+    ///
+    /// If an error occurs while writing to `writer`, it is returned.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (stack, weight) in &self.stacks {
+            writeln!(writer, "{} {}", stack, weight)?;
+        }
+        Ok(())
+    }
+
+    fn fold<S: AsRef<str>>(&self, group_id: &AllocationGroupId, stack: &[S]) -> String {
+        let mut folded = String::new();
+        if self.group_frames {
+            push_frame(&mut folded, &group_name(group_id));
+        }
+        for frame in stack.iter().rev() {
+            push_frame(&mut folded, frame.as_ref());
+        }
+        if folded.is_empty() {
+            push_frame(&mut folded, "[unknown]");
+        }
+        folded
+    }
+}
+
+fn push_frame(folded: &mut String, frame: &str) {
+    if !folded.is_empty() {
+        folded.push(';');
+    }
+
+    // Semicolons separate frames, and newlines separate stacks, so neither can appear in a frame.
+    folded.extend(frame.chars().map(|c| match c {
+        ';' => ':',
+        '\n' | '\r' => ' ',
+        c => c,
+    }));
+}
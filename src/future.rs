@@ -0,0 +1,68 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::token::AllocationGroupHandle;
+
+/// Extension trait for attaching an allocation group to a [`Future`].
+///
+/// As [`AllocationGuard`][crate::AllocationGuard] is `!Send`, it cannot be held across an `.await`
+/// in a future that may be moved between threads.  Instead, the future can be wrapped so that the
+/// allocation group is entered each time the future is polled, and exited again before `poll`
+/// returns, which attributes every allocation the future makes while running to the group no
+/// matter which thread it runs on.  This is the allocation group equivalent of
+/// `tracing::Instrument`, and doesn't require the `tracing-compat` feature.
+pub trait AllocationGroupExt: Future + Sized {
+    /// Wraps this future so that the given allocation group is active whenever it is polled.
+    ///
+    /// Either an [`AllocationGroupToken`][crate::AllocationGroupToken] or an
+    /// [`AllocationGroupHandle`] can be given, with the latter allowing the same allocation group
+    /// to be attached to many futures.
+    fn with_allocation_group<G>(self, group: G) -> AllocationGroupFuture<Self>
+    where
+        G: Into<AllocationGroupHandle>,
+    {
+        AllocationGroupFuture {
+            inner: self,
+            group: group.into(),
+        }
+    }
+}
+
+impl<F: Future> AllocationGroupExt for F {}
+
+/// A future with an allocation group attached to it.
+///
+/// Created by [`AllocationGroupExt::with_allocation_group`].
+#[derive(Debug)]
+pub struct AllocationGroupFuture<F> {
+    inner: F,
+    group: AllocationGroupHandle,
+}
+
+impl<F> AllocationGroupFuture<F> {
+    /// Gets the allocation group attached to this future.
+    pub fn group(&self) -> &AllocationGroupHandle {
+        &self.group
+    }
+
+    /// Consumes this wrapper, returning the wrapped future.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F: Future> Future for AllocationGroupFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `inner` is structurally pinned, and is never moved out of while pinned, as the only
+        // way to move it out is `into_inner`, which takes `self` by value.  `group` is never pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+
+        this.group.in_scope(|| inner.poll(cx))
+    }
+}
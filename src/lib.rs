@@ -38,6 +38,7 @@
 #![warn(clippy::all)]
 #![warn(clippy::cargo)]
 use std::{
+    cell::Cell,
     error, fmt,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
@@ -46,15 +47,65 @@ use std::{
 };
 
 mod allocator;
+mod canary;
+mod chrome;
+mod config;
+mod dhat;
+mod event_log;
+mod folded;
+mod future;
+#[cfg(feature = "metrics-compat")]
+mod metrics;
+mod pprof;
+mod prometheus;
+#[cfg(feature = "rayon-compat")]
+#[cfg_attr(docsrs, doc(cfg(feature = "rayon-compat")))]
+pub mod rayon;
+mod replay;
+#[cfg(feature = "shared-memory")]
+mod shared_memory;
+#[cfg(unix)]
+mod signal;
+#[cfg(unix)]
+mod socket;
+mod spawn;
+mod stats;
+mod timeseries;
 mod token;
+#[cfg(feature = "tokio-compat")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio-compat")))]
+pub mod tokio;
 #[cfg(feature = "tracing-compat")]
 mod tracing;
 mod util;
+mod worker;
 
 pub use crate::allocator::Allocator;
-pub use crate::token::{AllocationGroupId, AllocationGroupToken, AllocationGuard};
+use crate::canary::report_corruption;
+pub use crate::chrome::ChromeTraceSampler;
+pub use crate::dhat::DhatTracker;
+pub use crate::event_log::{AllocationEvent, EventLogReader, EventLogTracker};
+pub use crate::folded::FoldedStacks;
+pub use crate::future::{AllocationGroupExt, AllocationGroupFuture};
+#[cfg(feature = "metrics-compat")]
+pub use crate::metrics::MetricsPublisher;
+pub use crate::pprof::PprofProfile;
+pub use crate::replay::{Replay, ReplayReport};
+#[cfg(feature = "shared-memory")]
+pub use crate::shared_memory::{SharedMemoryReader, SharedMemoryTracker};
+#[cfg(unix)]
+pub use crate::signal::SignalDump;
+#[cfg(unix)]
+pub use crate::socket::{EventStreamReader, SocketTracker};
+pub use crate::spawn::{spawn_in_current_group, ThreadBuilderExt};
+pub use crate::stats::{GroupStats, RetiredStats, StatsTracker};
+pub use crate::timeseries::{TimeSeries, TimeSeriesSample, TimeSeriesSampler};
+pub use crate::token::{
+    AllocationGroupHandle, AllocationGroupId, AllocationGroupToken, AllocationGuard, Ancestors,
+};
 #[cfg(feature = "tracing-compat")]
 pub use crate::tracing::AllocationLayer;
+pub use crate::worker::WorkerHandle;
 
 /// Whether or not allocations should be tracked.
 static TRACKING_ENABLED: AtomicBool = AtomicBool::new(false);
@@ -64,6 +115,15 @@ static TRACKING_ENABLED: AtomicBool = AtomicBool::new(false);
 static mut GLOBAL_TRACKER: Option<Tracker> = None;
 static GLOBAL_INIT: AtomicUsize = AtomicUsize::new(UNINITIALIZED);
 
+thread_local! {
+    /// Whether or not tracking is suspended on this thread.
+    ///
+    /// Tracking is suspended while the global tracker is being called, so that any allocations made
+    /// by the tracker itself are not tracked, as well as when explicitly requested via
+    /// [`AllocationRegistry::untracked`].
+    static TRACKING_SUSPENDED: Cell<bool> = const { Cell::new(false) };
+}
+
 const UNINITIALIZED: usize = 0;
 const INITIALIZING: usize = 1;
 const INITIALIZED: usize = 2;
@@ -76,33 +136,162 @@ pub trait AllocationTracker {
     ///
     /// ## Correctness
     ///
-    /// Care should be taken to avoid allocating or deallocating in this method itself, as it could
-    /// cause a recursive call that overflows the stack.  Likewise, care should be taken to avoid
-    /// utilizing resources which depend on mutual exclusion i.e. locks which protect resources that
-    /// may allocate, as this can potentially lead to deadlocking if not capable of reentrant
-    /// locking
+    /// Allocations and deallocations made within this method are not themselves tracked, so they
+    /// cannot cause a recursive call that overflows the stack.  However, care should be taken when
+    /// utilizing resources which depend on mutual exclusion i.e. locks: if another thread holds
+    /// the same lock while allocating, that allocation will call back into this method, and
+    /// deadlock.  Code outside of the tracker that holds such a lock should do so within
+    /// [`AllocationRegistry::untracked`].
     ///
-    /// Implementors should prefer data structures that can pre-allocate their memory, such as
-    /// bounded channels, as well as intermediate structures that can be allocated entirely on the
-    /// stack.  This will ensure that no allocations are required in this method, while still
-    /// allowing code to be written that isn't unnecessarily restrictive.
+    /// Implementors should still prefer data structures that can pre-allocate their memory, such
+    /// as bounded channels, as well as intermediate structures that can be allocated entirely on
+    /// the stack, as allocating here adds overhead to every tracked allocation.
     fn allocated(&self, addr: usize, size: usize, group_id: AllocationGroupId);
 
     /// Tracks when a deallocation has occurred.
     ///
     /// ## Correctness
     ///
-    /// Care should be taken to avoid allocating or deallocating in this method itself, as it could
-    /// cause a recursive call that overflows the stack.  Likewise, care should be taken to avoid
-    /// utilizing resources which depend on mutual exclusion i.e. locks which protect resources that
-    /// may allocate, as this can potentially lead to deadlocking if not capable of reentrant
-    /// locking
+    /// Allocations and deallocations made within this method are not themselves tracked, so they
+    /// cannot cause a recursive call that overflows the stack.  However, care should be taken when
+    /// utilizing resources which depend on mutual exclusion i.e. locks: if another thread holds
+    /// the same lock while allocating, that allocation will call back into this method, and
+    /// deadlock.  Code outside of the tracker that holds such a lock should do so within
+    /// [`AllocationRegistry::untracked`].
     ///
-    /// Implementors should prefer data structures that can pre-allocate their memory, such as
-    /// bounded channels, as well as intermediate structures that can be allocated entirely on the
-    /// stack.  This will ensure that no allocations are required in this method, while still
-    /// allowing code to be written that isn't unnecessarily restrictive.
+    /// Implementors should still prefer data structures that can pre-allocate their memory, such
+    /// as bounded channels, as well as intermediate structures that can be allocated entirely on
+    /// the stack, as allocating here adds overhead to every tracked allocation.
     fn deallocated(&self, addr: usize, current_group_id: AllocationGroupId);
+
+    /// Tracks when an allocation was found to have overwritten its canaries.
+    ///
+    /// This is only called when buffer overflow detection has been enabled via
+    /// [`Allocator::with_canaries`], and is called just before the corrupted allocation is
+    /// deallocated.  `group_id` is the allocation group that was active when the allocation was
+    /// made, rather than the group that is active during the deallocation.
+    ///
+    /// The default implementation writes the address, size, and group of the allocation to
+    /// standard error, and then aborts the process.
+    ///
+    /// ## Correctness
+    ///
+    /// The same care must be taken here as in [`allocated`][AllocationTracker::allocated] when
+    /// utilizing resources which depend on mutual exclusion.
+    fn corrupted(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        report_corruption(addr, size, group_id)
+    }
+
+    /// Tracks when an allocation group has been registered, along with the tags it was registered
+    /// with, if any.
+    ///
+    /// This allows trackers to set up any per-group state ahead of time, rather than when the
+    /// first allocation is made within the group.  The parent of the group, if any, can be looked
+    /// up with [`AllocationGroupId::parent`].
+    ///
+    /// Like [`released`][AllocationTracker::released], this is called whenever a global tracker is
+    /// set, even if tracking is disabled.  Groups registered before the global tracker was set are
+    /// not reported here, but are still reported when they are released.
+    ///
+    /// The default implementation does nothing.
+    ///
+    /// ## Correctness
+    ///
+    /// The same care must be taken here as in [`allocated`][AllocationTracker::allocated] when
+    /// utilizing resources which depend on mutual exclusion.
+    fn registered(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        let _ = (group_id, tags);
+    }
+
+    /// Tracks when an allocation group has been entered, becoming the active allocation group on
+    /// the current thread.
+    ///
+    /// This is called whenever a token or handle is entered, including by
+    /// [`in_scope`][crate::AllocationGroupToken::in_scope] and each time a future with an
+    /// allocation group attached is polled, so it should be cheap.  As with allocations, it is only
+    /// called while tracking is enabled.
+    ///
+    /// The default implementation does nothing.
+    ///
+    /// ## Correctness
+    ///
+    /// The same care must be taken here as in [`allocated`][AllocationTracker::allocated] when
+    /// utilizing resources which depend on mutual exclusion.
+    fn entered(&self, group_id: AllocationGroupId) {
+        let _ = group_id;
+    }
+
+    /// Tracks when an allocation group has been exited, restoring the previously active allocation
+    /// group on the current thread, if any.
+    ///
+    /// As with [`entered`][AllocationTracker::entered], this is only called while tracking is
+    /// enabled, so a group that was entered while tracking was disabled may be exited without
+    /// having been entered, and vice versa.
+    ///
+    /// The default implementation does nothing.
+    ///
+    /// ## Correctness
+    ///
+    /// The same care must be taken here as in [`allocated`][AllocationTracker::allocated] when
+    /// utilizing resources which depend on mutual exclusion.
+    fn exited(&self, group_id: AllocationGroupId) {
+        let _ = group_id;
+    }
+
+    /// Tracks when an allocation group has been released, along with the tags it was registered
+    /// with, if any.
+    ///
+    /// Allocation groups are released once their token, and every handle, guard, and child group
+    /// that refers to them, has been dropped.  Once this method returns, the index of the group may
+    /// be reused by a newly registered group, with a new generation, so trackers that keep
+    /// per-group state by index should fold it away here, such as into a bucket for retired groups.
+    /// Allocations made within the group may still be live, and be deallocated later on.
+    ///
+    /// Unlike the other methods, this is called whenever a global tracker is set, even if tracking
+    /// is disabled, so that trackers never mix up state between the groups that share an index.
+    ///
+    /// The default implementation does nothing.
+    ///
+    /// ## Correctness
+    ///
+    /// The same care must be taken here as in [`allocated`][AllocationTracker::allocated] when
+    /// utilizing resources which depend on mutual exclusion.
+    fn released(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        let _ = (group_id, tags);
+    }
+}
+
+impl<T> AllocationTracker for Arc<T>
+where
+    T: AllocationTracker + ?Sized,
+{
+    fn allocated(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        (**self).allocated(addr, size, group_id)
+    }
+
+    fn deallocated(&self, addr: usize, current_group_id: AllocationGroupId) {
+        (**self).deallocated(addr, current_group_id)
+    }
+
+    fn corrupted(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        (**self).corrupted(addr, size, group_id)
+    }
+
+    fn registered(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        (**self).registered(group_id, tags)
+    }
+
+    fn entered(&self, group_id: AllocationGroupId) {
+        (**self).entered(group_id)
+    }
+
+    fn exited(&self, group_id: AllocationGroupId) {
+        (**self).exited(group_id)
+    }
+
+    fn released(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        (**self).released(group_id, tags)
+    }
 }
 
 struct Tracker {
@@ -128,6 +317,31 @@ impl Tracker {
     fn deallocated(&self, addr: usize, group_id: AllocationGroupId) {
         self.tracker.deallocated(addr, group_id)
     }
+
+    /// Tracks when an allocation was found to have overwritten its canaries.
+    fn corrupted(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        self.tracker.corrupted(addr, size, group_id)
+    }
+
+    /// Tracks when an allocation group has been registered.
+    fn registered(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        self.tracker.registered(group_id, tags)
+    }
+
+    /// Tracks when an allocation group has been entered.
+    fn entered(&self, group_id: AllocationGroupId) {
+        self.tracker.entered(group_id)
+    }
+
+    /// Tracks when an allocation group has been exited.
+    fn exited(&self, group_id: AllocationGroupId) {
+        self.tracker.exited(group_id)
+    }
+
+    /// Tracks when an allocation group has been released.
+    fn released(&self, group_id: AllocationGroupId, tags: &[(String, String)]) {
+        self.tracker.released(group_id, tags)
+    }
 }
 
 /// Returned if trying to set the global tracker fails.
@@ -158,6 +372,20 @@ impl AllocationRegistry {
         TRACKING_ENABLED.store(false, Ordering::SeqCst);
     }
 
+    /// Runs the given closure without tracking any allocations made on the current thread.
+    ///
+    /// This is primarily useful for code that shares state with a tracker, such as a background
+    /// thread that periodically reports on data collected by the tracker.  If that code were to
+    /// allocate while holding a lock that the tracker also acquires, the tracker would be called
+    /// for the allocation and try to acquire the same lock, deadlocking.
+    pub fn untracked<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let _suspended = SuspendGuard::suspend();
+        f()
+    }
+
     /// Sets the global tracker.
     ///
     /// Setting a global tracker does not enable or disable the tracking of allocations, so callers
@@ -212,6 +440,72 @@ impl AllocationRegistry {
     }
 }
 
+/// Suspends tracking on the current thread until dropped, restoring the previous state.
+pub(crate) struct SuspendGuard {
+    previous: Option<bool>,
+}
+
+impl SuspendGuard {
+    pub(crate) fn suspend() -> Self {
+        let previous = TRACKING_SUSPENDED
+            .try_with(|suspended| suspended.replace(true))
+            .ok();
+        Self { previous }
+    }
+}
+
+impl Drop for SuspendGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            let _ = TRACKING_SUSPENDED.try_with(|suspended| suspended.set(previous));
+        }
+    }
+}
+
+/// Calls the given closure with the global tracker, if tracking is enabled and a tracker is set.
+///
+/// Tracking is suspended on the current thread for the duration of the call, so that allocations
+/// made by the tracker do not recurse back into it.  If tracking is already suspended on this
+/// thread, or the thread-local state is no longer available because the thread is exiting, the
+/// closure is not called.
+#[inline(always)]
+pub(crate) fn with_global_tracker<F>(f: F)
+where
+    F: FnOnce(&Tracker),
+{
+    if let Some(tracker) = get_global_tracker() {
+        let suspended = TRACKING_SUSPENDED
+            .try_with(|suspended| suspended.replace(true))
+            .unwrap_or(true);
+        if !suspended {
+            f(tracker);
+            let _ = TRACKING_SUSPENDED.try_with(|suspended| suspended.set(false));
+        }
+    }
+}
+
+/// Calls the given closure with the global tracker, if a tracker is set, whether or not tracking is
+/// enabled or suspended.
+///
+/// This is used for notifications that trackers must not miss, rather than for allocation events.
+/// Tracking is suspended on the current thread for the duration of the call, so that allocations
+/// made by the tracker are not tracked.
+pub(crate) fn with_installed_tracker<F>(f: F)
+where
+    F: FnOnce(&Tracker),
+{
+    if GLOBAL_INIT.load(Ordering::SeqCst) != INITIALIZED {
+        return;
+    }
+
+    let tracker = unsafe {
+        (*std::ptr::addr_of!(GLOBAL_TRACKER))
+            .as_ref()
+            .expect("global tracked marked as initialized, but failed to unwrap")
+    };
+    AllocationRegistry::untracked(|| f(tracker));
+}
+
 #[inline(always)]
 fn get_global_tracker() -> Option<&'static Tracker> {
     // If tracking isn't enabled, then there's no point returning the tracker.
@@ -225,7 +519,7 @@ fn get_global_tracker() -> Option<&'static Tracker> {
     }
 
     unsafe {
-        let tracker = GLOBAL_TRACKER
+        let tracker = (*std::ptr::addr_of!(GLOBAL_TRACKER))
             .as_ref()
             .expect("global tracked marked as initialized, but failed to unwrap");
         Some(tracker)
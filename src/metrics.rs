@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ::metrics::{counter, gauge, Label};
+
+use crate::{stats::StatsTracker, token::AllocationGroupId, worker::WorkerHandle};
+
+/// Periodically publishes per-group allocation statistics through the [`metrics`][metrics] facade.
+///
+/// Statistics are read from a [`StatsTracker`], which must be installed as the global tracker, and
+/// published from a background thread, so the `metrics` recorder is never called from within the
+/// allocation path.  Each metric is labeled with `allocation_group`, holding the ID of the
+/// allocation group as formatted by its [`Display`][std::fmt::Display] implementation, as well as
+/// with the tags of the allocation group.
+///
+/// The following metrics are published:
+///
+/// - `tracking_allocator_allocations_total` (counter)
+/// - `tracking_allocator_deallocations_total` (counter)
+/// - `tracking_allocator_allocated_bytes_total` (counter)
+/// - `tracking_allocator_freed_bytes_total` (counter)
+/// - `tracking_allocator_live_allocations` (gauge)
+/// - `tracking_allocator_live_bytes` (gauge)
+/// - `tracking_allocator_allocation_rate_bytes` (gauge), the number of bytes allocated per second
+///   since the previous publish
+///
+/// [metrics]: https://docs.rs/metrics
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics-compat")))]
+pub struct MetricsPublisher {
+    stats: Arc<StatsTracker>,
+    interval: Duration,
+}
+
+impl MetricsPublisher {
+    /// Creates a new `MetricsPublisher` that reads from the given tracker.
+    ///
+    /// Statistics are published every second by default.
+    pub fn new(stats: Arc<StatsTracker>) -> Self {
+        Self {
+            stats,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the interval at which statistics are published.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Spawns a background thread that publishes statistics until the returned handle is finished
+    /// or dropped.
+    ///
+    /// Allocations made by the background thread, including those made by the `metrics` recorder,
+    /// are not tracked.
+    ///
+    /// # Errors
+    ///
+    /// If the background thread cannot be spawned, an error is returned.
+    pub fn spawn(self) -> io::Result<WorkerHandle> {
+        let mut labels = HashMap::new();
+        let mut previous = HashMap::new();
+        let mut last_publish = Instant::now();
+
+        WorkerHandle::spawn_periodic("tracking-allocator-metrics", self.interval, move |_| {
+            let elapsed = last_publish.elapsed().as_secs_f64();
+            last_publish = Instant::now();
+
+            let mut current = HashMap::new();
+            for stats in self.stats.snapshot() {
+                let group_id = stats.group_id;
+                let labels = labels
+                    .entry(group_id)
+                    .or_insert_with(|| group_labels(&group_id));
+
+                counter!("tracking_allocator_allocations_total", labels.iter())
+                    .absolute(stats.allocations);
+                counter!("tracking_allocator_deallocations_total", labels.iter())
+                    .absolute(stats.deallocations);
+                counter!("tracking_allocator_allocated_bytes_total", labels.iter())
+                    .absolute(stats.allocated_bytes);
+                counter!("tracking_allocator_freed_bytes_total", labels.iter())
+                    .absolute(stats.freed_bytes);
+                gauge!("tracking_allocator_live_allocations", labels.iter())
+                    .set(stats.live_allocations() as f64);
+                gauge!("tracking_allocator_live_bytes", labels.iter())
+                    .set(stats.live_bytes() as f64);
+
+                let previous_bytes = previous.get(&group_id).copied().unwrap_or(0);
+                if elapsed > 0.0 {
+                    let rate =
+                        stats.allocated_bytes.saturating_sub(previous_bytes) as f64 / elapsed;
+                    gauge!("tracking_allocator_allocation_rate_bytes", labels.iter()).set(rate);
+                }
+                current.insert(group_id, stats.allocated_bytes);
+            }
+
+            // Groups that have been released no longer show up in the snapshot, so we forget about
+            // them, rather than holding on to their labels for as long as the publisher runs.
+            labels.retain(|group_id, _| current.contains_key(group_id));
+            previous = current;
+
+            Ok(())
+        })
+    }
+}
+
+fn group_labels(group_id: &AllocationGroupId) -> Vec<Label> {
+    let mut labels = vec![Label::new("allocation_group", group_id.to_string())];
+    labels.extend(
+        group_id
+            .tags()
+            .iter()
+            .filter(|(key, _)| key != "allocation_group")
+            .map(|(key, value)| Label::new(key.clone(), value.clone())),
+    );
+    labels
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use crate::{token::AllocationGroupId, util::write_varint};
+
+/// Label key used to attach the allocation group of a sample.
+const GROUP_LABEL: &str = "allocation_group";
+
+/// Label key used to attach the generation of the allocation group of a sample.
+const GENERATION_LABEL: &str = "allocation_group_generation";
+
+/// Builds a heap profile in the [pprof][pprof] protobuf format.
+///
+/// Samples are made up of an allocation group, the size of the allocation, and the stack that the
+/// allocation was made from.  Samples with an identical stack and allocation group are merged
+/// together, and the allocation group of each sample is attached as a numeric label named
+/// `allocation_group`, which allows profiles to be filtered or grouped by allocation group, such
+/// as with `go tool pprof -tagfocus` or `-tagroot`.  Samples from allocation groups that reuse the
+/// index of a released group are kept apart, and also carry the generation of the group as a
+/// numeric label named `allocation_group_generation`.
+///
+/// Stacks are provided as a list of function names, ordered from the innermost frame (where the
+/// allocation occurred) to the outermost frame, which is the same order that pprof itself expects.
+/// Capturing and symbolizing stacks is left to the caller, as doing so from within an allocation
+/// tracker requires care to avoid allocating.
+///
+/// The encoded profile is not compressed, which `pprof` accepts as-is.
+///
+/// [pprof]: https://github.com/google/pprof/blob/main/proto/profile.proto
+#[derive(Default)]
+pub struct PprofProfile {
+    strings: StringTable,
+    functions: HashMap<usize, u64>,
+    samples: HashMap<(Vec<u64>, AllocationGroupId), SampleValues>,
+    period: i64,
+}
+
+#[derive(Default)]
+struct SampleValues {
+    count: i64,
+    bytes: i64,
+}
+
+impl PprofProfile {
+    /// Creates an empty `PprofProfile`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the sampling period, in bytes, that was used when sampling allocations.
+    ///
+    /// This is purely informational, and is passed through to pprof as the period of the profile.
+    /// It defaults to zero, meaning that the sampling period is unknown.
+    pub fn set_sampling_period(&mut self, period: usize) {
+        self.period = period as i64;
+    }
+
+    /// Adds an allocation sample to the profile.
+    ///
+    /// `stack` must be ordered from the innermost frame to the outermost frame.
+    pub fn add_sample<S>(&mut self, group_id: &AllocationGroupId, size: usize, stack: &[S])
+    where
+        S: AsRef<str>,
+    {
+        let locations = stack
+            .iter()
+            .map(|frame| self.function_id(frame.as_ref()))
+            .collect::<Vec<_>>();
+
+        let values = self.samples.entry((locations, *group_id)).or_default();
+        values.count += 1;
+        values.bytes += size as i64;
+    }
+
+    /// Encodes the profile into its protobuf representation.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut strings = self.strings.clone();
+        let objects = strings.intern("allocations");
+        let count = strings.intern("count");
+        let space = strings.intern("space");
+        let bytes = strings.intern("bytes");
+        let group_label = strings.intern(GROUP_LABEL);
+        let generation_label = strings.intern(GENERATION_LABEL);
+
+        let mut profile = Encoder::default();
+
+        // sample_type
+        profile.message(1, |m| {
+            m.int64(1, objects);
+            m.int64(2, count);
+        });
+        profile.message(1, |m| {
+            m.int64(1, space);
+            m.int64(2, bytes);
+        });
+
+        // sample
+        let mut samples = self.samples.iter().collect::<Vec<_>>();
+        samples.sort_by(|a, b| a.0.cmp(b.0));
+        for ((locations, group_id), values) in samples {
+            profile.message(2, |m| {
+                m.packed_uint64(1, locations.iter().copied());
+                m.packed_int64(2, [values.count, values.bytes].iter().copied());
+                m.message(3, |label| {
+                    label.int64(1, group_label);
+                    label.int64(3, group_id.as_usize() as i64);
+                });
+                if group_id.generation() != 0 {
+                    m.message(3, |label| {
+                        label.int64(1, generation_label);
+                        label.int64(3, i64::from(group_id.generation()));
+                    });
+                }
+            });
+        }
+
+        // location and function
+        //
+        // Each function gets exactly one location, with the same ID, as we only know the name of
+        // each frame and nothing about its address.
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by_key(|(_, id)| **id);
+        for (_, id) in &functions {
+            profile.message(4, |m| {
+                m.uint64(1, **id);
+                m.message(4, |line| line.uint64(1, **id));
+            });
+        }
+        for (name, id) in &functions {
+            profile.message(5, |m| {
+                m.uint64(1, **id);
+                m.int64(2, **name as i64);
+            });
+        }
+
+        // string_table
+        for s in &strings.strings {
+            profile.bytes(6, s.as_bytes());
+        }
+
+        // period_type and period
+        profile.message(11, |m| {
+            m.int64(1, space);
+            m.int64(2, bytes);
+        });
+        profile.int64(12, self.period);
+
+        profile.buf
+    }
+
+    /// Encodes the profile and writes it to the given writer.
+    ///
+    /// # Errors
+    ///
+    /// If an error occurs while writing to `writer`, it is returned.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.encode())
+    }
+
+    fn function_id(&mut self, name: &str) -> u64 {
+        let name = self.strings.intern(name) as usize;
+        let next_id = self.functions.len() as u64 + 1;
+        *self.functions.entry(name).or_insert(next_id)
+    }
+}
+
+/// Deduplicated string table, where index zero is always the empty string.
+#[derive(Clone)]
+struct StringTable {
+    strings: Vec<String>,
+    indexes: HashMap<String, i64>,
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        let mut indexes = HashMap::new();
+        indexes.insert(String::new(), 0);
+
+        Self {
+            strings: vec![String::new()],
+            indexes,
+        }
+    }
+}
+
+impl StringTable {
+    fn intern(&mut self, s: &str) -> i64 {
+        if let Some(index) = self.indexes.get(s) {
+            return *index;
+        }
+
+        let index = self.strings.len() as i64;
+        self.strings.push(s.to_string());
+        self.indexes.insert(s.to_string(), index);
+        index
+    }
+}
+
+/// Minimal protobuf encoder, covering only the wire types that the pprof format needs.
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn varint(&mut self, value: u64) {
+        write_varint(&mut self.buf, value);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(u64::from(field) << 3 | u64::from(wire_type));
+    }
+
+    fn uint64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.key(field, 0);
+            self.varint(value);
+        }
+    }
+
+    fn int64(&mut self, field: u32, value: i64) {
+        self.uint64(field, value as u64);
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, 2);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    fn message<F>(&mut self, field: u32, f: F)
+    where
+        F: FnOnce(&mut Encoder),
+    {
+        let mut inner = Encoder::default();
+        f(&mut inner);
+        self.bytes(field, &inner.buf);
+    }
+
+    fn packed_uint64<I>(&mut self, field: u32, values: I)
+    where
+        I: Iterator<Item = u64>,
+    {
+        let mut inner = Encoder::default();
+        values.for_each(|value| inner.varint(value));
+        if !inner.buf.is_empty() {
+            self.bytes(field, &inner.buf);
+        }
+    }
+
+    fn packed_int64<I>(&mut self, field: u32, values: I)
+    where
+        I: Iterator<Item = i64>,
+    {
+        self.packed_uint64(field, values.map(|value| value as u64));
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt::Write as _;
+
+use crate::stats::{GroupStats, StatsTracker};
+
+/// Label that every series carries, identifying the allocation group.
+const GROUP_LABEL: &str = "allocation_group";
+
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&GroupStats) -> u64,
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "tracking_allocator_allocations_total",
+        kind: "counter",
+        help: "Number of allocations made within the allocation group.",
+        value: |stats| stats.allocations,
+    },
+    Metric {
+        name: "tracking_allocator_deallocations_total",
+        kind: "counter",
+        help: "Number of allocations made within the allocation group that have been deallocated.",
+        value: |stats| stats.deallocations,
+    },
+    Metric {
+        name: "tracking_allocator_allocated_bytes_total",
+        kind: "counter",
+        help: "Number of bytes allocated within the allocation group.",
+        value: |stats| stats.allocated_bytes,
+    },
+    Metric {
+        name: "tracking_allocator_freed_bytes_total",
+        kind: "counter",
+        help: "Number of bytes allocated within the allocation group that have been deallocated.",
+        value: |stats| stats.freed_bytes,
+    },
+    Metric {
+        name: "tracking_allocator_live_allocations",
+        kind: "gauge",
+        help: "Number of allocations made within the allocation group that are still live.",
+        value: |stats| stats.live_allocations(),
+    },
+    Metric {
+        name: "tracking_allocator_live_bytes",
+        kind: "gauge",
+        help: "Number of bytes allocated within the allocation group that are still live.",
+        value: |stats| stats.live_bytes(),
+    },
+];
+
+impl StatsTracker {
+    /// Renders the statistics of every allocation group in the Prometheus text exposition format.
+    ///
+    /// Each series is labeled with `allocation_group`, holding the ID of the allocation group as
+    /// formatted by its [`Display`][std::fmt::Display] implementation, so that groups reusing the
+    /// index of a released group get series of their own, as
+    /// well as with the tags of the allocation group.  Tag keys are sanitized to be valid label
+    /// names, and a tag named `allocation_group` is ignored.  If several tag keys sanitize to the
+    /// same label name, all but the first are suffixed with `_2`, `_3`, and so on.
+    ///
+    /// The following metrics are rendered:
+    ///
+    /// - `tracking_allocator_allocations_total` (counter)
+    /// - `tracking_allocator_deallocations_total` (counter)
+    /// - `tracking_allocator_allocated_bytes_total` (counter)
+    /// - `tracking_allocator_freed_bytes_total` (counter)
+    /// - `tracking_allocator_live_allocations` (gauge)
+    /// - `tracking_allocator_live_bytes` (gauge)
+    ///
+    /// The output is compatible with OpenMetrics parsers that accept the Prometheus text format, and
+    /// can be served as-is from a `/metrics` endpoint.
+    pub fn render_prometheus(&self) -> String {
+        let groups = self
+            .snapshot()
+            .into_iter()
+            .map(|stats| {
+                let labels = render_labels(&stats);
+                (stats, labels)
+            })
+            .collect::<Vec<_>>();
+
+        let mut output = String::new();
+        for metric in METRICS {
+            let _ = writeln!(output, "# HELP {} {}", metric.name, metric.help);
+            let _ = writeln!(output, "# TYPE {} {}", metric.name, metric.kind);
+            for (stats, labels) in &groups {
+                let _ = writeln!(
+                    output,
+                    "{}{{{}}} {}",
+                    metric.name,
+                    labels,
+                    (metric.value)(stats)
+                );
+            }
+        }
+        output
+    }
+}
+
+fn render_labels(stats: &GroupStats) -> String {
+    let mut labels = format!("{}=\"{}\"", GROUP_LABEL, stats.group_id);
+    let mut names = vec![GROUP_LABEL.to_string()];
+    for (key, value) in stats.group_id.tags().iter() {
+        let key = sanitize_label_name(key);
+        if key == GROUP_LABEL {
+            continue;
+        }
+
+        // Distinct tag keys can sanitize to the same label name, which would render an invalid
+        // series, so later ones are suffixed with a counter until they're unique.
+        let mut unique = key.clone();
+        let mut suffix = 2;
+        while names.contains(&unique) {
+            unique = format!("{}_{}", key, suffix);
+            suffix += 1;
+        }
+        let _ = write!(labels, ",{}=\"", unique);
+        names.push(unique);
+        for c in value.chars() {
+            match c {
+                '\\' => labels.push_str("\\\\"),
+                '"' => labels.push_str("\\\""),
+                '\n' => labels.push_str("\\n"),
+                c => labels.push(c),
+            }
+        }
+        labels.push('"');
+    }
+    labels
+}
+
+fn sanitize_label_name(name: &str) -> String {
+    let mut sanitized = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if !sanitized.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
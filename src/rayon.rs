@@ -0,0 +1,226 @@
+//! Allocation group attribution for [`rayon`] parallelism.
+//!
+//! Rayon runs jobs on the threads of its thread pool, which don't have the allocation group of the
+//! code that created the job active, so the allocations made by parallel work are attributed to the
+//! root allocation group.  The functions in this module wrap their `rayon` counterparts so that the
+//! allocation group active when they are called is entered on the pool threads for the duration of
+//! each job, and [`ParallelIteratorExt::in_current_group`] does the same for parallel iterators.
+//!
+//! If no allocation group is active when a job is created, it runs without one, just as it would
+//! if it were created with `rayon` directly.
+use ::rayon::iter::{
+    plumbing::{Consumer, Folder, Reducer, UnindexedConsumer},
+    ParallelIterator,
+};
+
+use crate::token::AllocationGroupHandle;
+
+/// Runs two closures, potentially in parallel, within the current allocation group.
+///
+/// This is the allocation group-aware equivalent of [`rayon::join`].
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    let group = AllocationGroupHandle::current();
+    let group_b = group.clone();
+    ::rayon::join(
+        move || in_group(group.as_ref(), a),
+        move || in_group(group_b.as_ref(), b),
+    )
+}
+
+/// Spawns a job onto the global thread pool, within the current allocation group.
+///
+/// This is the allocation group-aware equivalent of [`rayon::spawn`].
+pub fn spawn<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let group = AllocationGroupHandle::current();
+    ::rayon::spawn(move || in_group(group.as_ref(), f));
+}
+
+/// Runs a closure on the global thread pool within the current allocation group, returning once
+/// it, and any jobs it spawned, have completed.
+///
+/// This is the allocation group-aware equivalent of [`rayon::scope`].  Only the closure itself is
+/// run within the allocation group, so jobs spawned onto the scope should be wrapped with
+/// [`in_current_group`] to be run within the allocation group as well.
+pub fn scope<'scope, F, R>(f: F) -> R
+where
+    F: FnOnce(&::rayon::Scope<'scope>) -> R + Send,
+    R: Send,
+{
+    let group = AllocationGroupHandle::current();
+    ::rayon::scope(move |scope| in_group(group.as_ref(), || f(scope)))
+}
+
+/// Wraps a closure so that it runs within the current allocation group, wherever it is called.
+///
+/// This is useful for jobs spawned onto a [`rayon::Scope`], or run with
+/// [`ThreadPool::install`][rayon::ThreadPool::install].
+pub fn in_current_group<F, R>(f: F) -> impl FnOnce() -> R + Send
+where
+    F: FnOnce() -> R + Send,
+{
+    let group = AllocationGroupHandle::current();
+    move || in_group(group.as_ref(), f)
+}
+
+/// Extension trait for running parallel iterators within the current allocation group.
+pub trait ParallelIteratorExt: ParallelIterator {
+    /// Wraps this parallel iterator so that the allocation group active when this is called is
+    /// entered while items are processed on the thread pool.
+    ///
+    /// The allocation group covers this iterator and every adapter applied after it, so it should
+    /// be called directly on the source of the parallel iterator, such as
+    /// `items.par_iter().in_current_group().map(...)`.  The resulting iterator is not an
+    /// [`IndexedParallelIterator`][rayon::iter::IndexedParallelIterator], so adapters like `zip`
+    /// and `enumerate` must be applied before it.
+    fn in_current_group(self) -> InAllocationGroup<Self> {
+        InAllocationGroup {
+            base: self,
+            group: AllocationGroupHandle::current(),
+        }
+    }
+}
+
+impl<I: ParallelIterator> ParallelIteratorExt for I {}
+
+/// A parallel iterator that runs within an allocation group.
+///
+/// Created by [`ParallelIteratorExt::in_current_group`].
+#[derive(Debug)]
+pub struct InAllocationGroup<I> {
+    base: I,
+    group: Option<AllocationGroupHandle>,
+}
+
+impl<I: ParallelIterator> ParallelIterator for InAllocationGroup<I> {
+    type Item = I::Item;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let consumer = GroupConsumer {
+            base: consumer,
+            group: self.group,
+        };
+        self.base.drive_unindexed(consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        self.base.opt_len()
+    }
+}
+
+struct GroupConsumer<C> {
+    base: C,
+    group: Option<AllocationGroupHandle>,
+}
+
+impl<C> GroupConsumer<C> {
+    fn wrap<T>(&self, base: T) -> GroupConsumer<T> {
+        GroupConsumer {
+            base,
+            group: self.group.clone(),
+        }
+    }
+}
+
+impl<T, C: Consumer<T>> Consumer<T> for GroupConsumer<C> {
+    type Folder = GroupConsumer<C::Folder>;
+    type Reducer = GroupConsumer<C::Reducer>;
+    type Result = C::Result;
+
+    fn split_at(self, index: usize) -> (Self, Self, Self::Reducer) {
+        let (left, right, reducer) = self.base.split_at(index);
+        (
+            GroupConsumer {
+                base: left,
+                group: self.group.clone(),
+            },
+            GroupConsumer {
+                base: right,
+                group: self.group.clone(),
+            },
+            GroupConsumer {
+                base: reducer,
+                group: self.group,
+            },
+        )
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        GroupConsumer {
+            base: self.base.into_folder(),
+            group: self.group,
+        }
+    }
+
+    fn full(&self) -> bool {
+        self.base.full()
+    }
+}
+
+impl<T, C: UnindexedConsumer<T>> UnindexedConsumer<T> for GroupConsumer<C> {
+    fn split_off_left(&self) -> Self {
+        self.wrap(self.base.split_off_left())
+    }
+
+    fn to_reducer(&self) -> Self::Reducer {
+        self.wrap(self.base.to_reducer())
+    }
+}
+
+impl<T, F: Folder<T>> Folder<T> for GroupConsumer<F> {
+    type Result = F::Result;
+
+    fn consume(self, item: T) -> Self {
+        let Self { base, group } = self;
+        let base = in_group(group.as_ref(), || base.consume(item));
+        Self { base, group }
+    }
+
+    fn consume_iter<I>(self, iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        // Entering the allocation group once for the whole batch is much cheaper than entering it
+        // for every item.
+        let Self { base, group } = self;
+        let base = in_group(group.as_ref(), || base.consume_iter(iter));
+        Self { base, group }
+    }
+
+    fn complete(self) -> Self::Result {
+        let Self { base, group } = self;
+        in_group(group.as_ref(), || base.complete())
+    }
+
+    fn full(&self) -> bool {
+        self.base.full()
+    }
+}
+
+impl<T, R: Reducer<T>> Reducer<T> for GroupConsumer<R> {
+    fn reduce(self, left: T, right: T) -> T {
+        let Self { base, group } = self;
+        in_group(group.as_ref(), || base.reduce(left, right))
+    }
+}
+
+fn in_group<F, R>(group: Option<&AllocationGroupHandle>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    match group {
+        Some(group) => group.in_scope(f),
+        None => f(),
+    }
+}
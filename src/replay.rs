@@ -0,0 +1,237 @@
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    collections::{BTreeMap, HashMap},
+    io, ptr,
+    sync::atomic::{AtomicPtr, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::event_log::AllocationEvent;
+
+/// Placeholder stored for allocations that the allocator under test failed to make.
+const FAILED: *mut u8 = usize::MAX as *mut u8;
+
+enum Op {
+    Alloc { slot: usize, layout: Layout },
+    Dealloc { slot: usize, layout: Layout },
+}
+
+/// Replays a recorded sequence of allocation events against an arbitrary allocator.
+///
+/// The events of each recorded thread are replayed, in order, on their own thread, such that the
+/// allocator under test sees the same sequence of allocations and deallocations, from the same
+/// number of threads, as the recorded workload.  When an allocation is deallocated on a different
+/// thread than it was allocated on, the deallocating thread waits until the allocation has been
+/// replayed before deallocating it.
+///
+/// As `Allocator` passes reallocations through to its own `alloc` and `dealloc`, reallocations are
+/// recorded, and replayed, as an allocation followed by a deallocation.  Alignment is not recorded,
+/// so allocations are replayed with an alignment of 16 bytes, or the smallest power of two that is
+/// at least their size, whichever is smaller.
+///
+/// Deallocations of allocations made before recording started are skipped, and allocations still
+/// live at the end of the recording are deallocated once the replay has finished timing.
+pub struct Replay {
+    threads: Vec<Vec<Op>>,
+    slots: usize,
+}
+
+/// The results of a replay.
+#[derive(Clone, Debug)]
+pub struct ReplayReport {
+    /// Wall-clock time taken to replay every thread.
+    pub elapsed: Duration,
+
+    /// Peak resident set size of the process during the replay, in bytes.
+    ///
+    /// This is only available on Linux, and is the peak for the whole process, so it includes
+    /// memory used to hold the replay itself.
+    pub peak_rss: Option<u64>,
+
+    /// Number of allocations replayed.
+    pub allocations: u64,
+
+    /// Number of deallocations replayed.
+    pub deallocations: u64,
+
+    /// Number of allocations that the allocator failed to make.
+    pub failed_allocations: u64,
+}
+
+impl Replay {
+    /// Prepares a replay from a sequence of recorded events, such as those read by
+    /// [`EventLogReader`][crate::EventLogReader].
+    ///
+    /// # Errors
+    ///
+    /// If any of the events is an error, it is returned.
+    pub fn from_events<I>(events: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = io::Result<AllocationEvent>>,
+    {
+        let mut threads = BTreeMap::<u64, Vec<Op>>::new();
+        let mut live = HashMap::new();
+        let mut slots = 0;
+
+        for event in events {
+            match event? {
+                AllocationEvent::Allocated {
+                    thread, addr, size, ..
+                } => {
+                    let layout = replay_layout(size);
+                    let slot = slots;
+                    slots += 1;
+
+                    live.insert(addr, (slot, layout));
+                    threads
+                        .entry(thread)
+                        .or_default()
+                        .push(Op::Alloc { slot, layout });
+                }
+                AllocationEvent::Deallocated { thread, addr, .. } => {
+                    if let Some((slot, layout)) = live.remove(&addr) {
+                        threads
+                            .entry(thread)
+                            .or_default()
+                            .push(Op::Dealloc { slot, layout });
+                    }
+                }
+            }
+        }
+
+        // Anything still live at the end of the recording gets cleaned up after the replay, so we
+        // give it its own thread that is run separately.
+        let mut cleanup = live
+            .into_values()
+            .map(|(slot, layout)| Op::Dealloc { slot, layout })
+            .collect::<Vec<_>>();
+        cleanup.sort_by_key(|op| match op {
+            Op::Alloc { slot, .. } | Op::Dealloc { slot, .. } => *slot,
+        });
+
+        let mut threads = threads.into_values().collect::<Vec<_>>();
+        threads.push(cleanup);
+
+        Ok(Self { threads, slots })
+    }
+
+    /// Runs the replay against the given allocator.
+    pub fn run<A>(&self, allocator: &A) -> ReplayReport
+    where
+        A: GlobalAlloc + Sync,
+    {
+        let slots = (0..self.slots)
+            .map(|_| AtomicPtr::new(ptr::null_mut()))
+            .collect::<Vec<_>>();
+        let allocations = AtomicU64::new(0);
+        let deallocations = AtomicU64::new(0);
+        let failed_allocations = AtomicU64::new(0);
+
+        let (cleanup, threads) = self
+            .threads
+            .split_last()
+            .expect("cleanup thread always exists");
+
+        reset_peak_rss();
+        let start = Instant::now();
+        thread::scope(|s| {
+            let (slots, allocations, deallocations, failed_allocations) =
+                (&slots, &allocations, &deallocations, &failed_allocations);
+            for ops in threads {
+                s.spawn(move || {
+                    let counts = replay_thread(allocator, slots, ops);
+                    allocations.fetch_add(counts.0, Ordering::Relaxed);
+                    deallocations.fetch_add(counts.1, Ordering::Relaxed);
+                    failed_allocations.fetch_add(counts.2, Ordering::Relaxed);
+                });
+            }
+        });
+        let elapsed = start.elapsed();
+        let peak_rss = read_peak_rss();
+
+        replay_thread(allocator, &slots, cleanup);
+
+        ReplayReport {
+            elapsed,
+            peak_rss,
+            allocations: allocations.into_inner(),
+            deallocations: deallocations.into_inner(),
+            failed_allocations: failed_allocations.into_inner(),
+        }
+    }
+}
+
+fn replay_layout(size: usize) -> Layout {
+    let align = size.max(1).next_power_of_two().min(16);
+    Layout::from_size_align(size, align).expect("alignment is a power of two no larger than 16")
+}
+
+/// Replays the operations of a single thread, returning the number of allocations, deallocations,
+/// and failed allocations.
+fn replay_thread<A: GlobalAlloc>(
+    allocator: &A,
+    slots: &[AtomicPtr<u8>],
+    ops: &[Op],
+) -> (u64, u64, u64) {
+    let (mut allocations, mut deallocations, mut failed) = (0, 0, 0);
+
+    for op in ops {
+        match op {
+            Op::Alloc { slot, layout } => {
+                let mut ptr = unsafe { allocator.alloc(*layout) };
+                if ptr.is_null() {
+                    ptr = FAILED;
+                    failed += 1;
+                } else {
+                    allocations += 1;
+                }
+                slots[*slot].store(ptr, Ordering::Release);
+            }
+            Op::Dealloc { slot, layout } => {
+                // The allocation may have been made on another thread which hasn't gotten to it
+                // yet, so wait for it.
+                let ptr = loop {
+                    let ptr = slots[*slot].swap(ptr::null_mut(), Ordering::Acquire);
+                    if !ptr.is_null() {
+                        break ptr;
+                    }
+                    thread::yield_now();
+                };
+
+                if ptr != FAILED {
+                    unsafe { allocator.dealloc(ptr, *layout) };
+                    deallocations += 1;
+                }
+            }
+        }
+    }
+
+    (allocations, deallocations, failed)
+}
+
+#[cfg(target_os = "linux")]
+fn reset_peak_rss() {
+    // Writing `5` resets the peak resident set size of the process, so that it only reflects the
+    // replay itself.  If it fails, we'll just end up including whatever came before.
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reset_peak_rss() {}
+
+#[cfg(target_os = "linux")]
+fn read_peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|kb| kb.trim().parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_peak_rss() -> Option<u64> {
+    None
+}
@@ -0,0 +1,402 @@
+use std::{
+    fs::OpenOptions,
+    io,
+    path::Path,
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use memmap2::MmapMut;
+
+use crate::{
+    event_log::{thread_index, AllocationEvent},
+    token::AllocationGroupId,
+    AllocationTracker,
+};
+
+const MAGIC: &[u8; 5] = b"TASHM";
+const VERSION: u32 = 2;
+
+const KIND_ALLOCATED: u32 = 0;
+const KIND_DEALLOCATED: u32 = 1;
+
+const MAGIC_OFFSET: usize = 0;
+const VERSION_OFFSET: usize = 8;
+const RECORD_SIZE_OFFSET: usize = 12;
+const CAPACITY_OFFSET: usize = 16;
+const DROPPED_OFFSET: usize = 24;
+const WRITE_POSITION_OFFSET: usize = 64;
+const READ_POSITION_OFFSET: usize = 128;
+const HEADER_SIZE: usize = 192;
+
+const RECORD_SIZE: usize = 48;
+
+/// An [`AllocationTracker`] that writes allocation events into a ring buffer in shared memory, for
+/// consumption by a reader in another process.
+///
+/// The ring buffer lives in a memory-mapped file, which would typically be placed on a `tmpfs`
+/// such as `/dev/shm` so that it's never written back to disk.  Writing an event only touches
+/// memory, with no system calls involved, making this the lowest-overhead way of getting events
+/// out of the process.  The ring buffer can be read with [`SharedMemoryReader`].
+///
+/// If the reader falls behind and the ring buffer fills up, new events are dropped, and counted in
+/// the header, rather than waiting on the reader.  Like [`StatsTracker`][crate::StatsTracker], the
+/// tracker takes a lock for every event, so that events from different threads can share the ring
+/// buffer.  Only one tracker may write to a given ring buffer at a time.
+///
+/// ## Layout
+///
+/// The file is laid out as a 192-byte header followed by the records of the ring buffer.  All
+/// integers are little-endian, except for the dropped event count and the write and read
+/// positions, which are accessed as atomic 64-bit integers and so are in the native byte order of
+/// the machine.  As the ring buffer can only be shared between processes on the same machine, the
+/// writer and reader always agree on that byte order.  The header is made up of:
+///
+/// | offset | size | contents |
+/// |--------|------|----------|
+/// | 0      | 8    | magic bytes `TASHM`, padded with zeroes |
+/// | 8      | 4    | version, currently `2` |
+/// | 12     | 4    | size of each record, in bytes, currently `48` |
+/// | 16     | 8    | capacity of the ring buffer, in records |
+/// | 24     | 8    | number of events dropped because the ring buffer was full, native-endian |
+/// | 64     | 8    | write position, native-endian |
+/// | 128    | 8    | read position, native-endian |
+///
+/// The write and read positions are each on their own cache line, and count records, rather than
+/// bytes, from the start of the ring buffer's life, so they only ever increase.  The record at
+/// position `n` is stored at offset `192 + (n % capacity) * 48`.  The writer only ever writes the
+/// write position, and the reader only ever writes the read position, with both accessed as
+/// atomic 64-bit integers: records from the read position up to, but not including, the write
+/// position are ready to be read, and the writer will not write past the read position plus the
+/// capacity.
+///
+/// Each record is made up of:
+///
+/// | offset | size | contents |
+/// |--------|------|----------|
+/// | 0      | 4    | kind, `0` for an allocation and `1` for a deallocation |
+/// | 4      | 4    | thread index |
+/// | 8      | 8    | timestamp, in nanoseconds since the ring buffer was created |
+/// | 16     | 8    | address |
+/// | 24     | 8    | allocation group index |
+/// | 32     | 8    | size, in bytes, for allocations, and zero for deallocations |
+/// | 40     | 4    | allocation group generation |
+/// | 44     | 4    | reserved, always zero |
+///
+/// As with [`EventLogTracker`][crate::EventLogTracker], thread indexes are small integers assigned
+/// to threads in the order they first allocate, and the group ID of a deallocation is the
+/// allocation group that was active when the deallocation was made.
+#[cfg_attr(docsrs, doc(cfg(feature = "shared-memory")))]
+pub struct SharedMemoryTracker {
+    start: Instant,
+    ring: Ring,
+    // Serializes writers, as only one writer may write to the ring buffer at a time.
+    lock: Mutex<()>,
+}
+
+impl SharedMemoryTracker {
+    /// Creates a new ring buffer at `path`, able to hold `capacity` events, and a tracker that
+    /// writes to it.
+    ///
+    /// If a file already exists at `path`, it is replaced.
+    ///
+    /// # Errors
+    ///
+    /// If `capacity` is zero, or the file cannot be created or mapped, an error is returned.
+    pub fn create<P: AsRef<Path>>(path: P, capacity: usize) -> io::Result<SharedMemoryTracker> {
+        if capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ring buffer capacity must be non-zero",
+            ));
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((HEADER_SIZE + capacity * RECORD_SIZE) as u64)?;
+        let ring = Ring::new(unsafe { MmapMut::map_mut(&file)? });
+
+        let mut header = [0u8; DROPPED_OFFSET];
+        header[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC.len()].copy_from_slice(MAGIC);
+        header[VERSION_OFFSET..VERSION_OFFSET + 4].copy_from_slice(&VERSION.to_le_bytes());
+        header[RECORD_SIZE_OFFSET..RECORD_SIZE_OFFSET + 4]
+            .copy_from_slice(&(RECORD_SIZE as u32).to_le_bytes());
+        header[CAPACITY_OFFSET..CAPACITY_OFFSET + 8]
+            .copy_from_slice(&(capacity as u64).to_le_bytes());
+        ring.write(0, &header);
+
+        Ok(SharedMemoryTracker {
+            start: Instant::now(),
+            ring: ring.with_capacity(capacity as u64),
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Gets the number of events that have been dropped because the ring buffer was full.
+    pub fn dropped_events(&self) -> u64 {
+        self.ring.atomic(DROPPED_OFFSET).load(Ordering::Relaxed)
+    }
+
+    fn record(&self, kind: u32, addr: usize, group_id: AllocationGroupId, size: usize) {
+        let timestamp = self.start.elapsed().as_nanos() as u64;
+        let thread = thread_index() as u32;
+
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        // We're the only writer, so our own view of the write position is always current, but we
+        // need to synchronize with the reader to know which records it's done with.
+        let write_position = self
+            .ring
+            .atomic(WRITE_POSITION_OFFSET)
+            .load(Ordering::Relaxed);
+        let read_position = self
+            .ring
+            .atomic(READ_POSITION_OFFSET)
+            .load(Ordering::Acquire);
+        if write_position.wrapping_sub(read_position) >= self.ring.capacity {
+            self.ring
+                .atomic(DROPPED_OFFSET)
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let mut record = [0u8; RECORD_SIZE];
+        record[0..4].copy_from_slice(&kind.to_le_bytes());
+        record[4..8].copy_from_slice(&thread.to_le_bytes());
+        record[8..16].copy_from_slice(&timestamp.to_le_bytes());
+        record[16..24].copy_from_slice(&(addr as u64).to_le_bytes());
+        record[24..32].copy_from_slice(&(group_id.as_usize() as u64).to_le_bytes());
+        record[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+        record[40..44].copy_from_slice(&group_id.generation().to_le_bytes());
+        self.ring
+            .write(self.ring.record_offset(write_position), &record);
+
+        self.ring
+            .atomic(WRITE_POSITION_OFFSET)
+            .store(write_position + 1, Ordering::Release);
+    }
+}
+
+impl AllocationTracker for SharedMemoryTracker {
+    fn allocated(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        self.record(KIND_ALLOCATED, addr, group_id, size);
+    }
+
+    fn deallocated(&self, addr: usize, current_group_id: AllocationGroupId) {
+        self.record(KIND_DEALLOCATED, addr, current_group_id, 0);
+    }
+}
+
+/// Reads allocation events from a ring buffer written by [`SharedMemoryTracker`].
+///
+/// Reading consumes events, freeing up space in the ring buffer for the writer, so only one reader
+/// should read from a given ring buffer at a time.
+#[cfg_attr(docsrs, doc(cfg(feature = "shared-memory")))]
+pub struct SharedMemoryReader {
+    ring: Ring,
+}
+
+impl SharedMemoryReader {
+    /// Opens the ring buffer at `path`, validating its header.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be opened or mapped, or is not a supported ring buffer, an error is
+    /// returned.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<SharedMemoryReader> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let ring = Ring::new(unsafe { MmapMut::map_mut(&file)? });
+
+        let mut header = [0u8; DROPPED_OFFSET];
+        if ring.len >= HEADER_SIZE {
+            ring.read(0, &mut header);
+        }
+        if &header[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC.len()] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a tracking-allocator ring buffer",
+            ));
+        }
+        let version = read_u32(&header, VERSION_OFFSET);
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported ring buffer version {}", version),
+            ));
+        }
+        let record_size = read_u32(&header, RECORD_SIZE_OFFSET) as usize;
+        let capacity = read_u64(&header, CAPACITY_OFFSET);
+        if record_size != RECORD_SIZE
+            || capacity == 0
+            || (ring.len - HEADER_SIZE) as u64 / RECORD_SIZE as u64 != capacity
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "ring buffer header does not match its size",
+            ));
+        }
+
+        Ok(SharedMemoryReader {
+            ring: ring.with_capacity(capacity),
+        })
+    }
+
+    /// Reads every event that is currently available, appending them to `events`, and returns how
+    /// many were read.
+    ///
+    /// This never waits for new events to be written, so callers will typically poll it
+    /// periodically.
+    ///
+    /// # Errors
+    ///
+    /// If a record has an unknown kind, an error is returned.  Records before the invalid record
+    /// have still been appended to `events`, and the invalid record is skipped, so that the reader
+    /// can continue on with the next record.
+    pub fn read_available(&mut self, events: &mut Vec<AllocationEvent>) -> io::Result<usize> {
+        let read_position = self
+            .ring
+            .atomic(READ_POSITION_OFFSET)
+            .load(Ordering::Relaxed);
+        let write_position = self
+            .ring
+            .atomic(WRITE_POSITION_OFFSET)
+            .load(Ordering::Acquire);
+
+        let mut position = read_position;
+        let mut result = Ok(());
+        let mut record = [0u8; RECORD_SIZE];
+        while position != write_position {
+            self.ring
+                .read(self.ring.record_offset(position), &mut record);
+            position += 1;
+
+            match decode_record(&record) {
+                Ok(event) => events.push(event),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        self.ring
+            .atomic(READ_POSITION_OFFSET)
+            .store(position, Ordering::Release);
+        result.map(|()| (position - read_position) as usize)
+    }
+
+    /// Gets the number of events that the writer has dropped because the ring buffer was full.
+    pub fn dropped_events(&self) -> u64 {
+        self.ring.atomic(DROPPED_OFFSET).load(Ordering::Relaxed)
+    }
+}
+
+fn decode_record(record: &[u8]) -> io::Result<AllocationEvent> {
+    let kind = read_u32(record, 0);
+    let thread = u64::from(read_u32(record, 4));
+    let timestamp = read_u64(record, 8);
+    let addr = read_u64(record, 16) as usize;
+    let group_id =
+        AllocationGroupId::from_parts(read_u64(record, 24) as usize, read_u32(record, 40));
+    let size = read_u64(record, 32) as usize;
+
+    match kind {
+        KIND_ALLOCATED => Ok(AllocationEvent::Allocated {
+            timestamp,
+            thread,
+            addr,
+            size,
+            group_id,
+        }),
+        KIND_DEALLOCATED => Ok(AllocationEvent::Deallocated {
+            timestamp,
+            thread,
+            addr,
+            current_group_id: group_id,
+        }),
+        kind => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown event kind {}", kind),
+        )),
+    }
+}
+
+/// A ring buffer mapping, shared with another process.
+///
+/// As the other process writes to the mapping concurrently, we never create references to it,
+/// other than to the atomic integers in the header.  Everything else is copied in and out through
+/// a pointer to the start of the mapping, which is taken once, when it's created.
+struct Ring {
+    // Only kept so that the mapping stays alive.
+    _map: MmapMut,
+    ptr: *mut u8,
+    len: usize,
+    capacity: u64,
+}
+
+// SAFETY: The mapping is owned by the ring, and is only accessed through atomics, or through
+// copies of records that the protocol gives one side exclusive access to.
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn new(mut map: MmapMut) -> Self {
+        let ptr = map.as_mut_ptr();
+        let len = map.len();
+        Self {
+            _map: map,
+            ptr,
+            len,
+            capacity: 0,
+        }
+    }
+
+    fn with_capacity(self, capacity: u64) -> Self {
+        Self { capacity, ..self }
+    }
+
+    fn record_offset(&self, position: u64) -> usize {
+        HEADER_SIZE + (position % self.capacity) as usize * RECORD_SIZE
+    }
+
+    /// Gets the atomic integer at `offset` in the mapping.
+    ///
+    /// The mapping is page-aligned, and every offset we use is a multiple of eight, so the integer
+    /// is always suitably aligned.
+    fn atomic(&self, offset: usize) -> &AtomicU64 {
+        assert!(offset + 8 <= self.len);
+        unsafe { &*(self.ptr.add(offset) as *const AtomicU64) }
+    }
+
+    /// Copies `buf.len()` bytes at `offset` in the mapping into `buf`.
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        assert!(offset + buf.len() <= self.len);
+        unsafe { ptr::copy_nonoverlapping(self.ptr.add(offset), buf.as_mut_ptr(), buf.len()) }
+    }
+
+    /// Copies `buf` into the mapping at `offset`.
+    fn write(&self, offset: usize, buf: &[u8]) {
+        assert!(offset + buf.len() <= self.len);
+        unsafe { ptr::copy_nonoverlapping(buf.as_ptr(), self.ptr.add(offset), buf.len()) }
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
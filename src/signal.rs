@@ -0,0 +1,157 @@
+use std::{
+    convert::TryFrom,
+    fs::OpenOptions,
+    io::{self, Write},
+    mem,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    stats::{render_report, StatsTracker},
+    worker::WorkerHandle,
+};
+
+/// Highest signal number that can be used to trigger a dump.
+const MAX_SIGNAL: usize = 64;
+
+/// How often the background thread checks whether a signal has arrived.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Whether each signal has arrived since it was last checked for.
+static PENDING: [AtomicBool; MAX_SIGNAL + 1] = [NOT_PENDING; MAX_SIGNAL + 1];
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    // Only async-signal-safe operations are allowed in here, so all we do is set a flag, and leave
+    // the actual dumping to the background thread.
+    if let Some(pending) = PENDING.get(signal as usize) {
+        pending.store(true, Ordering::Release);
+    }
+}
+
+/// Dumps per-group allocation statistics whenever the process receives a signal.
+///
+/// Statistics are read from a [`StatsTracker`], which must be installed as the global tracker.  The
+/// installed signal handler only sets a flag, and a background thread checks that flag every 100ms,
+/// so that dumping, which allocates and does I/O, never happens within the signal handler itself.
+/// Each dump lists the statistics of every allocation group that has had allocations tracked,
+/// followed by a leak report of the groups with the most live bytes, along with their tags.
+///
+/// By default, `SIGUSR1` triggers a dump, and dumps are written to standard error.  Only one
+/// `SignalDump` should be running for any given signal at a time.
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+pub struct SignalDump {
+    stats: Arc<StatsTracker>,
+    signal: libc::c_int,
+    path: Option<PathBuf>,
+}
+
+impl SignalDump {
+    /// Creates a new `SignalDump` that reads from the given tracker.
+    pub fn new(stats: Arc<StatsTracker>) -> Self {
+        Self {
+            stats,
+            signal: libc::SIGUSR1,
+            path: None,
+        }
+    }
+
+    /// Sets the signal that triggers a dump.
+    pub fn with_signal(mut self, signal: libc::c_int) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    /// Sets the file that dumps are written to.
+    ///
+    /// Each dump is appended to the file, which is opened anew for every dump, so it can be safely
+    /// rotated or removed in between dumps.
+    pub fn with_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Installs the signal handler, and spawns a background thread that dumps statistics whenever
+    /// the signal arrives, until the returned handle is finished or dropped.
+    ///
+    /// Once the handle is finished or dropped, the signal handler that was installed before is
+    /// restored.  Allocations made by the background thread are not tracked.
+    ///
+    /// # Errors
+    ///
+    /// If the signal handler cannot be installed, such as when the signal is out of range or cannot
+    /// be caught, or the background thread cannot be spawned, an error is returned.
+    pub fn spawn(self) -> io::Result<WorkerHandle> {
+        let signal = self.signal;
+        let pending = usize::try_from(signal)
+            .ok()
+            .and_then(|signal| PENDING.get(signal))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("signal {} is out of range", signal),
+                )
+            })?;
+        pending.store(false, Ordering::Release);
+
+        let previous = install_handler(signal)?;
+
+        let result =
+            WorkerHandle::spawn_periodic("tracking-allocator-signal", POLL_INTERVAL, move |done| {
+                if done {
+                    return restore_handler(signal, &previous);
+                }
+
+                if pending.swap(false, Ordering::Acquire) {
+                    self.dump()?;
+                }
+                Ok(())
+            });
+
+        if result.is_err() {
+            let _ = restore_handler(signal, &previous);
+        }
+        result
+    }
+
+    fn dump(&self) -> io::Result<()> {
+        let report = render_report(&self.stats.snapshot());
+        match &self.path {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(report.as_bytes()),
+            None => io::stderr().lock().write_all(report.as_bytes()),
+        }
+    }
+}
+
+fn install_handler(signal: libc::c_int) -> io::Result<libc::sigaction> {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut previous: libc::sigaction = mem::zeroed();
+        if libc::sigaction(signal, &action, &mut previous) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(previous)
+    }
+}
+
+fn restore_handler(signal: libc::c_int, previous: &libc::sigaction) -> io::Result<()> {
+    if unsafe { libc::sigaction(signal, previous, std::ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
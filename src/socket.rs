@@ -0,0 +1,409 @@
+use std::{
+    io::{self, BufReader, Read, Write},
+    mem,
+    os::unix::net::UnixStream,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event_log::{
+        read_header, read_varint_or_eof, thread_index, write_header, AllocationEvent, EventDecoder,
+        EventEncoder,
+    },
+    token::AllocationGroupId,
+    util::write_varint,
+    worker::WorkerHandle,
+    AllocationTracker,
+};
+
+const MAGIC: &[u8; 5] = b"TASTR";
+const VERSION: u8 = 2;
+
+/// Size at which a batch of encoded events is handed off to the background thread.
+const BATCH_SIZE: usize = 16 * 1024;
+
+/// Number of full batches that can be waiting on the background thread before batches are dropped.
+const BATCH_QUEUE_LEN: usize = 64;
+
+/// How often the background thread sends partially-filled batches, so that the collector stays
+/// current even when allocations are infrequent.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a write to the socket can block before the background thread gives up on the collector.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Upper bound on the size of a single batch that a reader will accept.
+const MAX_BATCH_SIZE: u64 = 64 * 1024 * 1024;
+
+/// An [`AllocationTracker`] that streams allocation events over a Unix domain socket to a
+/// collector in another process.
+///
+/// Events are encoded into batches, using the same record format as
+/// [`EventLogTracker`][crate::EventLogTracker], and full batches are handed off to a background
+/// thread, which writes them to the socket.  Unlike `EventLogTracker`, the tracker never waits on
+/// the background thread: if the socket can't keep up, or the collector has gone away, whole
+/// batches are dropped, and the number of events dropped is counted, both in
+/// [`dropped_events`][SocketTracker::dropped_events] and in the stream itself, so that the collector
+/// knows its view is incomplete.  If the collector stops reading altogether, writes time out, and
+/// every event from then on is dropped, so finishing or dropping the handle never hangs on the
+/// collector.  The stream can be read with [`EventStreamReader`], and the
+/// `tracking-allocator-collector` binary provides a collector that aggregates and prints per-group
+/// statistics.
+///
+/// ## Format
+///
+/// A stream starts with a header, made up of the magic bytes `TASTR` followed by a single version
+/// byte, which is currently `2`.  The header is followed by zero or more batches, each of which is
+/// made up of:
+///
+/// - the number of events dropped since the previous batch, as a LEB128 varint
+/// - the length of the batch's records, in bytes, as a LEB128 varint
+/// - the records themselves, encoded as in the event log format
+///
+/// The "previous" values that records are delta-encoded against start out as zero at the start of
+/// each batch, so that any batch can be dropped without affecting the batches that follow it.
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+pub struct SocketTracker {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    start: Instant,
+    dropped: AtomicU64,
+    state: Mutex<BatchState>,
+}
+
+struct BatchState {
+    encoder: EventEncoder,
+    batch: Batch,
+    batches: SyncSender<Batch>,
+    dropped_since_sent: u64,
+}
+
+#[derive(Default)]
+struct Batch {
+    dropped: u64,
+    events: u64,
+    buf: Vec<u8>,
+}
+
+impl SocketTracker {
+    /// Creates a new `SocketTracker`, connecting to the collector listening at `path`.
+    ///
+    /// A handle to the background thread is returned alongside the tracker.  Once finished, or
+    /// dropped, the handle sends any events that are still buffered and shuts down the connection.
+    /// Events which occur after that are dropped.  If a write to the socket fails, or times out, the
+    /// background thread exits early, counting any events it couldn't send as dropped, and the error
+    /// is returned from [`finish`][WorkerHandle::finish].
+    ///
+    /// # Errors
+    ///
+    /// If the socket cannot be connected to, the header cannot be written, or the background thread
+    /// cannot be spawned, an error is returned.
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<(SocketTracker, WorkerHandle)> {
+        Self::new(UnixStream::connect(path)?)
+    }
+
+    /// Creates a new `SocketTracker` that streams over an already-connected socket.
+    ///
+    /// # Errors
+    ///
+    /// If the header cannot be written, the write timeout cannot be set, or the background thread
+    /// cannot be spawned, an error is returned.
+    pub fn new(mut stream: UnixStream) -> io::Result<(SocketTracker, WorkerHandle)> {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        write_header(&mut stream, MAGIC, VERSION)?;
+
+        let (batches, batches_rx) = mpsc::sync_channel(BATCH_QUEUE_LEN);
+        let shared = Arc::new(Shared {
+            start: Instant::now(),
+            dropped: AtomicU64::new(0),
+            state: Mutex::new(BatchState {
+                encoder: EventEncoder::default(),
+                batch: Batch::with_capacity(BATCH_SIZE),
+                batches,
+                dropped_since_sent: 0,
+            }),
+        });
+
+        let worker_shared = Arc::clone(&shared);
+        let handle = WorkerHandle::spawn("tracking-allocator-socket", move |shutdown| {
+            let result = worker_shared.stream_batches(&mut stream, &batches_rx, &shutdown);
+            if result.is_err() {
+                worker_shared.abandon(batches_rx);
+            }
+            result
+        })?;
+
+        Ok((SocketTracker { shared }, handle))
+    }
+
+    /// Gets the total number of events that have been dropped because the background thread could
+    /// not keep up, or the collector had gone away.
+    pub fn dropped_events(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    fn record<F>(&self, event: F)
+    where
+        F: FnOnce(u64) -> AllocationEvent,
+    {
+        let mut state = self.shared.lock();
+        let state = &mut *state;
+
+        // As with `EventLogTracker`, timestamps have to be taken under the lock for events to be
+        // encoded in the order they occurred in.
+        let event = event(self.shared.start.elapsed().as_nanos() as u64);
+        state.encoder.encode(&mut state.batch.buf, &event);
+        state.batch.events += 1;
+        if state.batch.buf.len() >= BATCH_SIZE {
+            state.send(&self.shared.dropped);
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, BatchState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes batches to the socket as they're handed off, until shut down.
+    fn stream_batches(
+        &self,
+        stream: &mut UnixStream,
+        batches: &Receiver<Batch>,
+        shutdown: &Receiver<()>,
+    ) -> io::Result<()> {
+        let mut frame = Vec::new();
+        loop {
+            let done = match shutdown.try_recv() {
+                Err(TryRecvError::Empty) => false,
+                Ok(()) | Err(TryRecvError::Disconnected) => true,
+            };
+
+            match batches.recv_timeout(FLUSH_INTERVAL) {
+                Ok(batch) => self.write_batch(stream, &mut frame, &batch)?,
+                Err(RecvTimeoutError::Timeout) => {
+                    // Nothing has filled up a batch in a while, so send whatever is pending.
+                    for batch in self.take_pending(batches) {
+                        self.write_batch(stream, &mut frame, &batch)?;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if done {
+                break;
+            }
+        }
+
+        for batch in self.take_pending(batches) {
+            self.write_batch(stream, &mut frame, &batch)?;
+        }
+        stream.flush()?;
+        stream.shutdown(std::net::Shutdown::Write)
+    }
+
+    /// Writes a batch to the socket, counting its events as dropped if it can't be written.
+    fn write_batch(
+        &self,
+        stream: &mut UnixStream,
+        frame: &mut Vec<u8>,
+        batch: &Batch,
+    ) -> io::Result<()> {
+        let result = write_batch(stream, frame, batch);
+        if result.is_err() {
+            self.dropped.fetch_add(batch.events, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Gives up on the socket after a failed write, counting every event that is still waiting to be
+    /// written as dropped.
+    fn abandon(&self, batches: Receiver<Batch>) {
+        // Batches are only ever queued while holding the lock, so once the receiver is dropped with
+        // the lock held, every batch from then on is counted as dropped when it fails to be queued.
+        let mut state = self.lock();
+        let pending = batches.try_iter().map(|batch| batch.events).sum::<u64>();
+        drop(batches);
+
+        let partial = mem::take(&mut state.batch.events);
+        state.batch.buf.clear();
+        state.encoder = EventEncoder::default();
+        self.dropped.fetch_add(pending + partial, Ordering::Relaxed);
+    }
+
+    /// Takes every batch that is waiting to be written, in order, including the partially-filled
+    /// batch that is currently being encoded into.
+    fn take_pending(&self, batches: &Receiver<Batch>) -> Vec<Batch> {
+        // Batches are only ever queued while holding the lock, so holding it here means the
+        // partially-filled batch is guaranteed to come after everything in the queue.
+        let mut state = self.lock();
+        let mut pending = batches.try_iter().collect::<Vec<_>>();
+        if state.batch.events > 0 || state.dropped_since_sent > 0 {
+            let mut batch = mem::replace(&mut state.batch, Batch::with_capacity(BATCH_SIZE));
+            batch.dropped = mem::take(&mut state.dropped_since_sent);
+            state.encoder = EventEncoder::default();
+            pending.push(batch);
+        }
+        pending
+    }
+}
+
+impl BatchState {
+    fn send(&mut self, dropped: &AtomicU64) {
+        let mut batch = mem::replace(&mut self.batch, Batch::with_capacity(BATCH_SIZE));
+        batch.dropped = self.dropped_since_sent;
+        self.encoder = EventEncoder::default();
+
+        match self.batches.try_send(batch) {
+            Ok(()) => self.dropped_since_sent = 0,
+            Err(TrySendError::Full(batch)) | Err(TrySendError::Disconnected(batch)) => {
+                // Rather than waiting on the background thread, we drop the batch, and reuse its
+                // buffer for the next one.
+                self.dropped_since_sent += batch.events;
+                dropped.fetch_add(batch.events, Ordering::Relaxed);
+
+                let mut buf = batch.buf;
+                buf.clear();
+                self.batch = Batch {
+                    buf,
+                    ..Default::default()
+                };
+            }
+        }
+    }
+}
+
+impl Batch {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(capacity),
+            ..Default::default()
+        }
+    }
+}
+
+fn write_batch(stream: &mut UnixStream, frame: &mut Vec<u8>, batch: &Batch) -> io::Result<()> {
+    frame.clear();
+    write_varint(frame, batch.dropped);
+    write_varint(frame, batch.buf.len() as u64);
+    stream.write_all(frame)?;
+    stream.write_all(&batch.buf)
+}
+
+impl AllocationTracker for SocketTracker {
+    fn allocated(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        let thread = thread_index();
+        self.record(|timestamp| AllocationEvent::Allocated {
+            timestamp,
+            thread,
+            addr,
+            size,
+            group_id,
+        });
+    }
+
+    fn deallocated(&self, addr: usize, current_group_id: AllocationGroupId) {
+        let thread = thread_index();
+        self.record(|timestamp| AllocationEvent::Deallocated {
+            timestamp,
+            thread,
+            addr,
+            current_group_id,
+        });
+    }
+}
+
+/// Reads allocation events from a stream written by [`SocketTracker`].
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+pub struct EventStreamReader<R> {
+    reader: BufReader<R>,
+    batch: io::Cursor<Vec<u8>>,
+    decoder: EventDecoder,
+    dropped: u64,
+    failed: bool,
+}
+
+impl<R: Read> EventStreamReader<R> {
+    /// Creates a new `EventStreamReader`, reading and validating the header of the stream.
+    ///
+    /// # Errors
+    ///
+    /// If the header cannot be read, or is not a supported stream header, an error is returned.
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+
+        read_header(&mut reader, MAGIC, VERSION, "event stream")?;
+
+        Ok(Self {
+            reader,
+            batch: io::Cursor::new(Vec::new()),
+            decoder: EventDecoder::default(),
+            dropped: 0,
+            failed: false,
+        })
+    }
+
+    /// Gets the number of events that the sender has reported dropping so far.
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Reads the next batch, returning `false` if the stream has ended.
+    fn next_batch(&mut self) -> io::Result<bool> {
+        let dropped = match read_varint_or_eof(&mut self.reader)? {
+            Some(dropped) => dropped,
+            None => return Ok(false),
+        };
+        let len = read_varint_or_eof(&mut self.reader)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        if len > MAX_BATCH_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("batch of {} bytes is too large", len),
+            ));
+        }
+
+        let mut buf = mem::take(self.batch.get_mut());
+        buf.resize(len as usize, 0);
+        self.reader.read_exact(&mut buf)?;
+
+        self.dropped += dropped;
+        self.batch = io::Cursor::new(buf);
+        self.decoder = EventDecoder::default();
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for EventStreamReader<R> {
+    type Item = io::Result<AllocationEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        loop {
+            let result = match self.decoder.decode(&mut self.batch) {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => match self.next_batch() {
+                    Ok(true) => continue,
+                    Ok(false) => return None,
+                    Err(e) => e,
+                },
+                Err(e) => e,
+            };
+
+            // Once we've hit an error, we can't know where the next batch starts, so we stop.
+            self.failed = true;
+            return Some(Err(result));
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    io,
+    thread::{Builder, JoinHandle},
+};
+
+use crate::token::AllocationGroupHandle;
+
+/// Spawns a new thread that runs within the allocation group active on the current thread.
+///
+/// The active allocation group is tracked per thread, so a thread spawned with [`thread::spawn`][std::thread::spawn]
+/// starts out with no active allocation group, and its allocations are attributed to the root
+/// allocation group.  This captures the allocation group active on the current thread, if any, and
+/// enters it on the new thread before running `f`, so that the new thread's allocations are
+/// attributed to the same allocation group as those of the thread that spawned it.
+///
+/// # Panics
+///
+/// Panics if the thread cannot be spawned, just like [`thread::spawn`][std::thread::spawn].
+pub fn spawn_in_current_group<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new()
+        .spawn_in_current_group(f)
+        .expect("failed to spawn thread")
+}
+
+/// Extension trait for spawning threads from a [`Builder`] within the current allocation group.
+pub trait ThreadBuilderExt {
+    /// Spawns a new thread that runs within the allocation group active on the current thread.
+    ///
+    /// This is the [`Builder`] equivalent of [`spawn_in_current_group`].
+    ///
+    /// # Errors
+    ///
+    /// If the thread cannot be spawned, an error is returned, just like [`Builder::spawn`].
+    fn spawn_in_current_group<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static;
+}
+
+impl ThreadBuilderExt for Builder {
+    fn spawn_in_current_group<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let group = AllocationGroupHandle::current();
+        self.spawn(move || match group {
+            Some(group) => group.in_scope(f),
+            None => f(),
+        })
+    }
+}
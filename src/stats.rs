@@ -0,0 +1,586 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    hint,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{token::AllocationGroupId, AllocationRegistry, AllocationTracker};
+
+/// Number of shards used for the map of live allocations, to reduce lock contention.
+const LIVE_SHARDS: usize = 64;
+
+/// Number of groups listed in the leak report of a rendered report.
+const LEAK_REPORT_LEN: usize = 10;
+
+/// An [`AllocationTracker`] that aggregates allocation statistics per allocation group.
+///
+/// For each allocation group, the number of allocations and deallocations, as well as the number of
+/// bytes allocated and freed, are aggregated into atomic counters.  Deallocations are attributed to
+/// the allocation group that made the allocation, rather than the group active at the time of the
+/// deallocation, so the live bytes of each group can be derived from its counters.
+///
+/// In order to do so, `StatsTracker` keeps a map of every live allocation it has seen, and so
+/// allocates in order to track allocations.  This is safe as allocations made from within a tracker
+/// are not themselves tracked, but it does mean that every tracked allocation incurs some amount of
+/// overhead.  Allocations which were made before the tracker was installed, or while tracking was
+/// disabled, are ignored when they are deallocated.
+///
+/// When an allocation group is released, its statistics are folded into the statistics for retired
+/// groups, which can be read with [`retired`][StatsTracker::retired], so that the memory used by the
+/// tracker is bounded by the number of allocation groups registered at once, rather than by the
+/// number of groups ever registered.  Allocations made within a released group that are deallocated
+/// afterwards are attributed to the retired groups as well.
+///
+/// Allocations are only credited to the innermost allocation group active when they were made.
+/// Trackers created with [`with_inclusive_stats`][StatsTracker::with_inclusive_stats] also credit
+/// them to every enclosing group that was active, giving inclusive statistics for each group in the
+/// same way that a profiler gives the total time of a function alongside its self time.
+///
+/// `StatsTracker` is generally wrapped in an [`Arc`][std::sync::Arc] and installed as the global
+/// tracker, with a clone of the `Arc` used to read the statistics from elsewhere.
+pub struct StatsTracker {
+    groups: RwLock<Vec<GroupCounters>>,
+    inclusive_groups: Option<RwLock<Vec<GroupCounters>>>,
+    retired: GroupCounters,
+    retired_groups: AtomicU64,
+    live: Vec<Mutex<HashMap<usize, LiveAllocation>>>,
+}
+
+#[derive(Default)]
+struct GroupCounters {
+    /// The generation of the allocation group that the counters belong to, as counters are indexed
+    /// by the index of the allocation group, which is shared with every other group that has used
+    /// the same index.
+    generation: AtomicU32,
+
+    /// The number of deallocations that are in the middle of being credited to the counters, which
+    /// have to finish before the counters can be reset for the next group with the same index.
+    crediting: AtomicU32,
+    allocations: AtomicU64,
+    deallocations: AtomicU64,
+    allocated_bytes: AtomicU64,
+    freed_bytes: AtomicU64,
+}
+
+struct LiveAllocation {
+    group_id: AllocationGroupId,
+    size: u64,
+
+    /// The enclosing allocation groups that were active when the allocation was made, if inclusive
+    /// statistics are being aggregated.
+    enclosing: Box<[AllocationGroupId]>,
+}
+
+/// Allocation statistics for a single allocation group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupStats {
+    /// The allocation group these statistics belong to.
+    pub group_id: AllocationGroupId,
+
+    /// The number of allocations made within the group.
+    pub allocations: u64,
+
+    /// The number of allocations made within the group that have since been deallocated.
+    pub deallocations: u64,
+
+    /// The total number of bytes allocated within the group.
+    pub allocated_bytes: u64,
+
+    /// The total number of bytes allocated within the group that have since been deallocated.
+    pub freed_bytes: u64,
+}
+
+impl GroupStats {
+    /// The number of allocations made within the group that are still live.
+    pub fn live_allocations(&self) -> u64 {
+        self.allocations.saturating_sub(self.deallocations)
+    }
+
+    /// The number of bytes allocated within the group that are still live.
+    pub fn live_bytes(&self) -> u64 {
+        self.allocated_bytes.saturating_sub(self.freed_bytes)
+    }
+
+    fn empty(group_id: AllocationGroupId) -> Self {
+        Self {
+            group_id,
+            allocations: 0,
+            deallocations: 0,
+            allocated_bytes: 0,
+            freed_bytes: 0,
+        }
+    }
+
+    fn add(&mut self, other: &GroupStats) {
+        self.allocations += other.allocations;
+        self.deallocations += other.deallocations;
+        self.allocated_bytes += other.allocated_bytes;
+        self.freed_bytes += other.freed_bytes;
+    }
+}
+
+/// Allocation statistics for every allocation group that has been released.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetiredStats {
+    /// The number of released allocation groups that had allocations tracked.
+    pub groups: u64,
+
+    /// The number of allocations made within the released groups.
+    pub allocations: u64,
+
+    /// The number of allocations made within the released groups that have since been
+    /// deallocated.
+    pub deallocations: u64,
+
+    /// The total number of bytes allocated within the released groups.
+    pub allocated_bytes: u64,
+
+    /// The total number of bytes allocated within the released groups that have since been
+    /// deallocated.
+    pub freed_bytes: u64,
+}
+
+impl RetiredStats {
+    /// The number of allocations made within the released groups that are still live.
+    pub fn live_allocations(&self) -> u64 {
+        self.allocations.saturating_sub(self.deallocations)
+    }
+
+    /// The number of bytes allocated within the released groups that are still live.
+    pub fn live_bytes(&self) -> u64 {
+        self.allocated_bytes.saturating_sub(self.freed_bytes)
+    }
+}
+
+impl StatsTracker {
+    /// Creates a new `StatsTracker`.
+    pub fn new() -> Self {
+        Self {
+            groups: RwLock::new(Vec::new()),
+            inclusive_groups: None,
+            retired: GroupCounters::default(),
+            retired_groups: AtomicU64::new(0),
+            live: (0..LIVE_SHARDS).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// Creates a new `StatsTracker` that also aggregates inclusive statistics.
+    ///
+    /// Each allocation is credited to every allocation group that was active when it was made, as
+    /// given by [`AllocationRegistry::with_active_groups`], rather than only the innermost one.
+    /// Inclusive statistics can be read with [`group_inclusive`][StatsTracker::group_inclusive] and
+    /// [`snapshot_inclusive`][StatsTracker::snapshot_inclusive], while the exclusive statistics
+    /// read with [`group`][StatsTracker::group] and [`snapshot`][StatsTracker::snapshot] are
+    /// unaffected.  A group that is active more than once on the stack is only credited once.
+    ///
+    /// Unlike [`snapshot_rolled_up`][StatsTracker::snapshot_rolled_up], which follows the parents
+    /// that groups were registered with, this follows the groups that were actually entered, which
+    /// need not be related.  Remembering the enclosing groups of each live allocation makes this
+    /// more expensive than the default.
+    pub fn with_inclusive_stats() -> Self {
+        Self {
+            inclusive_groups: Some(RwLock::new(Vec::new())),
+            ..Self::new()
+        }
+    }
+
+    /// Gets the statistics for the given allocation group.
+    ///
+    /// If no allocations have been tracked for the group, `None` is returned.
+    pub fn group(&self, group_id: &AllocationGroupId) -> Option<GroupStats> {
+        load_group(&self.groups, group_id)
+    }
+
+    /// Gets the statistics for every allocation group that has had allocations tracked.
+    ///
+    /// Statistics are ordered by allocation group ID.
+    pub fn snapshot(&self) -> Vec<GroupStats> {
+        load_snapshot(&self.groups)
+    }
+
+    /// Gets the statistics for every allocation group that has been released.
+    pub fn retired(&self) -> RetiredStats {
+        let stats = self.retired.load(AllocationGroupId::root());
+        RetiredStats {
+            groups: self.retired_groups.load(Ordering::Relaxed),
+            allocations: stats.allocations,
+            deallocations: stats.deallocations,
+            allocated_bytes: stats.allocated_bytes,
+            freed_bytes: stats.freed_bytes,
+        }
+    }
+
+    /// Gets the inclusive statistics for the given allocation group, covering every allocation made
+    /// while the group was active, whether or not it was the innermost active group.
+    ///
+    /// If the tracker wasn't created with
+    /// [`with_inclusive_stats`][StatsTracker::with_inclusive_stats], or no allocations have been
+    /// tracked for the group, `None` is returned.
+    pub fn group_inclusive(&self, group_id: &AllocationGroupId) -> Option<GroupStats> {
+        load_group(self.inclusive_groups.as_ref()?, group_id)
+    }
+
+    /// Gets the inclusive statistics for every allocation group that has had allocations tracked.
+    ///
+    /// If the tracker wasn't created with
+    /// [`with_inclusive_stats`][StatsTracker::with_inclusive_stats], an empty snapshot is
+    /// returned.
+    ///
+    /// Statistics are ordered by allocation group ID.
+    pub fn snapshot_inclusive(&self) -> Vec<GroupStats> {
+        self.inclusive_groups
+            .as_ref()
+            .map(load_snapshot)
+            .unwrap_or_default()
+    }
+
+    /// Gets the statistics for every allocation group, with the statistics of each group including
+    /// those of all of its descendants.
+    ///
+    /// Allocation groups registered as children of another group, with
+    /// [`AllocationGroupToken::register_child`][crate::AllocationGroupToken::register_child], have
+    /// their statistics added to those of each of their ancestors, so that a group's statistics
+    /// cover everything allocated within the subtree rooted at it.  Ancestors that have not had any
+    /// allocations tracked themselves are included as long as one of their descendants has.
+    ///
+    /// Statistics are ordered by allocation group ID.
+    pub fn snapshot_rolled_up(&self) -> Vec<GroupStats> {
+        let snapshot = self.snapshot();
+
+        AllocationRegistry::untracked(|| {
+            let mut rolled_up = BTreeMap::new();
+            for stats in snapshot {
+                for id in stats.group_id.ancestors() {
+                    rolled_up
+                        .entry(id)
+                        .or_insert_with(|| GroupStats::empty(id))
+                        .add(&stats);
+                }
+                rolled_up
+                    .entry(stats.group_id)
+                    .or_insert_with(|| GroupStats::empty(stats.group_id))
+                    .add(&stats);
+            }
+            rolled_up.into_values().collect()
+        })
+    }
+
+    fn live_shard(&self, addr: usize) -> &Mutex<HashMap<usize, LiveAllocation>> {
+        // Allocations are at least word-aligned, so the lowest bits carry no information.
+        &self.live[(addr >> 4) % LIVE_SHARDS]
+    }
+
+    /// Runs the given closure with the inclusive counters of the given group, and each of the given
+    /// enclosing groups, crediting each group only once.
+    fn with_inclusive_counters<F>(
+        &self,
+        group_id: &AllocationGroupId,
+        enclosing: &[AllocationGroupId],
+        claim: bool,
+        f: F,
+    ) where
+        F: Fn(&GroupCounters),
+    {
+        if let Some(groups) = &self.inclusive_groups {
+            with_counters(groups, group_id, claim, &f);
+            for (i, id) in enclosing.iter().enumerate() {
+                if id != group_id && !enclosing[..i].contains(id) {
+                    with_counters(groups, id, claim, &f);
+                }
+            }
+        }
+    }
+}
+
+/// Runs the given closure with the counters of the given group, returning whether or not it was
+/// run.
+///
+/// As counters are shared by every group with the same index, they are only used if they belong to
+/// the generation of the given group.  If `claim` is set, as it is when allocating, the counters are
+/// claimed for the given group first.  This is safe as the previous group with the same index has
+/// already been released, so no more allocations can be made within it.
+///
+/// Deallocations, on the other hand, can race with the group being released, so they mark the
+/// counters as being credited before checking the generation, and [`reset_counters`] waits for
+/// them to finish before resetting the counters.  A deallocation is thereby either credited before
+/// the counters are reset, and retired along with them, or sees that the group has been released.
+fn with_counters<F>(
+    groups: &RwLock<Vec<GroupCounters>>,
+    group_id: &AllocationGroupId,
+    claim: bool,
+    f: F,
+) -> bool
+where
+    F: Fn(&GroupCounters),
+{
+    let index = group_id.as_usize();
+    let generation = group_id.generation();
+    let credit = |counters: &GroupCounters| {
+        if claim {
+            counters.generation.store(generation, Ordering::Relaxed);
+            f(counters);
+            return true;
+        }
+
+        counters.crediting.fetch_add(1, Ordering::SeqCst);
+        let current = counters.generation.load(Ordering::SeqCst) == generation;
+        if current {
+            f(counters);
+        }
+        counters.crediting.fetch_sub(1, Ordering::Release);
+        current
+    };
+
+    {
+        let groups = groups.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(counters) = groups.get(index) {
+            return credit(counters);
+        }
+    }
+
+    let mut groups = groups.write().unwrap_or_else(|e| e.into_inner());
+    if groups.len() <= index {
+        groups.resize_with(index + 1, GroupCounters::default);
+    }
+    credit(&groups[index])
+}
+
+fn load_group(
+    groups: &RwLock<Vec<GroupCounters>>,
+    group_id: &AllocationGroupId,
+) -> Option<GroupStats> {
+    let groups = groups.read().unwrap_or_else(|e| e.into_inner());
+    groups
+        .get(group_id.as_usize())
+        .filter(|counters| counters.generation.load(Ordering::Relaxed) == group_id.generation())
+        .filter(|counters| counters.allocations.load(Ordering::Relaxed) > 0)
+        .map(|counters| counters.load(*group_id))
+}
+
+fn load_snapshot(groups: &RwLock<Vec<GroupCounters>>) -> Vec<GroupStats> {
+    // We allocate while holding the lock that the tracker itself acquires, so we can't let our own
+    // allocations be tracked.
+    AllocationRegistry::untracked(|| {
+        let groups = groups.read().unwrap_or_else(|e| e.into_inner());
+        groups
+            .iter()
+            .enumerate()
+            .filter(|(_, counters)| counters.allocations.load(Ordering::Relaxed) > 0)
+            .map(|(index, counters)| {
+                let generation = counters.generation.load(Ordering::Relaxed);
+                counters.load(AllocationGroupId::from_parts(index, generation))
+            })
+            .collect()
+    })
+}
+
+/// Resets the counters of the given group, if it has any, so that they can be used by the next
+/// group with the same index, returning what was reset.
+fn reset_counters(
+    groups: &RwLock<Vec<GroupCounters>>,
+    group_id: &AllocationGroupId,
+) -> Option<GroupStats> {
+    let groups = groups.read().unwrap_or_else(|e| e.into_inner());
+    let counters = groups.get(group_id.as_usize())?;
+    if counters.generation.load(Ordering::Relaxed) != group_id.generation() {
+        return None;
+    }
+
+    // From here on, deallocations of allocations made within the group no longer match the
+    // generation of the counters, and so are not credited to them.  Any that already matched are
+    // still being credited, so we wait for them, otherwise they'd end up credited to the next group
+    // with the same index.
+    counters
+        .generation
+        .store(group_id.generation().wrapping_add(1), Ordering::SeqCst);
+    while counters.crediting.load(Ordering::SeqCst) > 0 {
+        hint::spin_loop();
+    }
+    Some(GroupStats {
+        group_id: *group_id,
+        allocations: counters.allocations.swap(0, Ordering::Relaxed),
+        deallocations: counters.deallocations.swap(0, Ordering::Relaxed),
+        allocated_bytes: counters.allocated_bytes.swap(0, Ordering::Relaxed),
+        freed_bytes: counters.freed_bytes.swap(0, Ordering::Relaxed),
+    })
+}
+
+impl Default for StatsTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AllocationTracker for StatsTracker {
+    fn allocated(&self, addr: usize, size: usize, group_id: AllocationGroupId) {
+        let size = size as u64;
+
+        let enclosing = if self.inclusive_groups.is_some() {
+            AllocationRegistry::with_active_groups(|groups| groups.into())
+        } else {
+            Box::default()
+        };
+
+        let credit = |counters: &GroupCounters| {
+            counters.allocations.fetch_add(1, Ordering::Relaxed);
+            counters.allocated_bytes.fetch_add(size, Ordering::Relaxed);
+        };
+        with_counters(&self.groups, &group_id, true, credit);
+        self.with_inclusive_counters(&group_id, &enclosing, true, credit);
+
+        self.live_shard(addr)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                addr,
+                LiveAllocation {
+                    group_id,
+                    size,
+                    enclosing,
+                },
+            );
+    }
+
+    fn deallocated(&self, addr: usize, _current_group_id: AllocationGroupId) {
+        let live = self
+            .live_shard(addr)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&addr);
+
+        if let Some(LiveAllocation {
+            group_id,
+            size,
+            enclosing,
+        }) = live
+        {
+            let credit = |counters: &GroupCounters| {
+                counters.deallocations.fetch_add(1, Ordering::Relaxed);
+                counters.freed_bytes.fetch_add(size, Ordering::Relaxed);
+            };
+            if !with_counters(&self.groups, &group_id, false, credit) {
+                credit(&self.retired);
+            }
+            self.with_inclusive_counters(&group_id, &enclosing, false, credit);
+        }
+    }
+
+    fn registered(&self, group_id: AllocationGroupId, _tags: &[(String, String)]) {
+        // Set up the counters for the group ahead of time, so that its first allocation doesn't
+        // have to take the write lock.
+        with_counters(&self.groups, &group_id, true, |_| {});
+        if let Some(groups) = &self.inclusive_groups {
+            with_counters(groups, &group_id, true, |_| {});
+        }
+    }
+
+    fn released(&self, group_id: AllocationGroupId, _tags: &[(String, String)]) {
+        if let Some(stats) = reset_counters(&self.groups, &group_id) {
+            if stats.allocations > 0 {
+                self.retired_groups.fetch_add(1, Ordering::Relaxed);
+            }
+            self.retired
+                .allocations
+                .fetch_add(stats.allocations, Ordering::Relaxed);
+            self.retired
+                .deallocations
+                .fetch_add(stats.deallocations, Ordering::Relaxed);
+            self.retired
+                .allocated_bytes
+                .fetch_add(stats.allocated_bytes, Ordering::Relaxed);
+            self.retired
+                .freed_bytes
+                .fetch_add(stats.freed_bytes, Ordering::Relaxed);
+        }
+
+        // Inclusive statistics overlap with those of other groups, so they're simply discarded.
+        if let Some(groups) = &self.inclusive_groups {
+            let _ = reset_counters(groups, &group_id);
+        }
+    }
+}
+
+impl GroupCounters {
+    fn load(&self, group_id: AllocationGroupId) -> GroupStats {
+        GroupStats {
+            group_id,
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            allocated_bytes: self.allocated_bytes.load(Ordering::Relaxed),
+            freed_bytes: self.freed_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Renders a human-readable report of the given statistics, listing every allocation group followed
+/// by the groups with the most live bytes.
+pub(crate) fn render_report(snapshot: &[GroupStats]) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "tracking-allocator report at {} (unix time)",
+        timestamp
+    );
+    let _ = writeln!(
+        out,
+        "  {:>8} {:>10} {:>10} {:>14} {:>14} {:>12} {:>14}",
+        "group", "allocs", "deallocs", "allocated", "freed", "live allocs", "live"
+    );
+    for stats in snapshot {
+        let _ = writeln!(
+            out,
+            "  {:>8} {:>10} {:>10} {:>14} {:>14} {:>12} {:>14}",
+            stats.group_id,
+            stats.allocations,
+            stats.deallocations,
+            stats.allocated_bytes,
+            stats.freed_bytes,
+            stats.live_allocations(),
+            stats.live_bytes()
+        );
+    }
+
+    let mut leaking = snapshot
+        .iter()
+        .filter(|stats| stats.live_bytes() > 0)
+        .collect::<Vec<_>>();
+    leaking.sort_by_key(|stats| Reverse(stats.live_bytes()));
+
+    let _ = writeln!(out, "top leaking groups:");
+    if leaking.is_empty() {
+        let _ = writeln!(out, "  (none)");
+    }
+    for stats in leaking.into_iter().take(LEAK_REPORT_LEN) {
+        let _ = write!(
+            out,
+            "  group {}: {} bytes in {} allocations",
+            stats.group_id,
+            stats.live_bytes(),
+            stats.live_allocations()
+        );
+
+        let tags = stats.group_id.tags();
+        if !tags.is_empty() {
+            let tags = tags
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>();
+            let _ = write!(out, " [{}]", tags.join(", "));
+        }
+        let _ = writeln!(out);
+    }
+    let _ = writeln!(out);
+
+    out
+}
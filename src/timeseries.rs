@@ -0,0 +1,163 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{stats::StatsTracker, token::AllocationGroupId, worker::WorkerHandle};
+
+/// Periodically samples the live bytes of each allocation group into an in-memory time series.
+///
+/// Statistics are read from a [`StatsTracker`], which must be installed as the global tracker, and
+/// sampled from a background thread.  Each allocation group gets its own bounded history, holding
+/// the most recent samples, with the oldest samples discarded as new ones are taken.  Once a group
+/// no longer shows up in the tracker's statistics, such as after it's released, its history is
+/// discarded as well, so the memory used by the time series depends only on the number of groups
+/// that are live, not on how long the sampler runs.  Histories are read through the [`TimeSeries`]
+/// handle returned when spawning the sampler.
+pub struct TimeSeriesSampler {
+    stats: Arc<StatsTracker>,
+    interval: Duration,
+    capacity: usize,
+}
+
+/// A single sample of an allocation group's usage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSeriesSample {
+    /// Time since the sampler was spawned.
+    pub elapsed: Duration,
+
+    /// The number of bytes allocated within the group that were still live.
+    pub live_bytes: u64,
+}
+
+/// Handle to the per-group histories collected by a [`TimeSeriesSampler`].
+///
+/// The handle can be cloned, and remains readable after the sampler has been stopped.
+#[derive(Clone)]
+pub struct TimeSeries {
+    inner: Arc<Mutex<Histories>>,
+}
+
+struct Histories {
+    capacity: usize,
+    groups: BTreeMap<AllocationGroupId, VecDeque<TimeSeriesSample>>,
+}
+
+impl TimeSeriesSampler {
+    /// Creates a new `TimeSeriesSampler` that reads from the given tracker.
+    ///
+    /// Samples are taken every second by default, and the 1024 most recent samples are kept for each
+    /// allocation group.
+    pub fn new(stats: Arc<StatsTracker>) -> Self {
+        Self {
+            stats,
+            interval: Duration::from_secs(1),
+            capacity: 1024,
+        }
+    }
+
+    /// Sets the interval at which samples are taken.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the number of samples kept for each allocation group.
+    ///
+    /// A capacity of zero is treated as a capacity of one.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Spawns a background thread that samples the tracker until the returned handle is finished or
+    /// dropped.
+    ///
+    /// Allocations made by the background thread are not tracked.
+    ///
+    /// # Errors
+    ///
+    /// If the background thread cannot be spawned, an error is returned.
+    pub fn spawn(self) -> io::Result<(TimeSeries, WorkerHandle)> {
+        let series = TimeSeries {
+            inner: Arc::new(Mutex::new(Histories {
+                capacity: self.capacity,
+                groups: BTreeMap::new(),
+            })),
+        };
+
+        let start = Instant::now();
+        let histories = series.clone();
+        let handle = WorkerHandle::spawn_periodic(
+            "tracking-allocator-time-series",
+            self.interval,
+            move |done| {
+                if done {
+                    return Ok(());
+                }
+
+                let elapsed = start.elapsed();
+                let snapshot = self.stats.snapshot();
+
+                let mut histories = histories.lock();
+                let capacity = histories.capacity;
+                for stats in &snapshot {
+                    let history = histories
+                        .groups
+                        .entry(stats.group_id)
+                        .or_insert_with(|| VecDeque::with_capacity(capacity));
+                    if history.len() == capacity {
+                        history.pop_front();
+                    }
+                    history.push_back(TimeSeriesSample {
+                        elapsed,
+                        live_bytes: stats.live_bytes(),
+                    });
+                }
+
+                // The snapshot is ordered by group ID, so it can be searched for groups that are gone.
+                histories.groups.retain(|group_id, _| {
+                    snapshot
+                        .binary_search_by(|stats| stats.group_id.cmp(group_id))
+                        .is_ok()
+                });
+                Ok(())
+            },
+        )?;
+
+        Ok((series, handle))
+    }
+}
+
+impl TimeSeries {
+    /// Gets the history of the given allocation group, ordered from oldest to newest.
+    ///
+    /// If the group has never been sampled, or its history has been discarded, an empty history is
+    /// returned.
+    pub fn history(&self, group_id: &AllocationGroupId) -> Vec<TimeSeriesSample> {
+        self.lock()
+            .groups
+            .get(group_id)
+            .map(|history| history.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Gets the most recent sample of the given allocation group.
+    pub fn latest(&self, group_id: &AllocationGroupId) -> Option<TimeSeriesSample> {
+        self.lock()
+            .groups
+            .get(group_id)
+            .and_then(|history| history.back().copied())
+    }
+
+    /// Gets every allocation group that has a history, ordered by allocation group ID.
+    pub fn groups(&self) -> Vec<AllocationGroupId> {
+        self.lock().groups.keys().copied().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Histories> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -1,44 +1,420 @@
 use std::{
     cell::RefCell,
+    fmt::{self, Write as _},
     mem,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, Mutex, MutexGuard, Weak},
 };
 
-use crate::util::PhantomNotSend;
+use crate::{util::PhantomNotSend, AllocationRegistry};
 
 thread_local! {
     /// The currently executing allocation token.
     ///
     /// Any allocations which occur on this thread will be associated with whichever token is
     /// present at the time of the allocation.
-    static CURRENT_ALLOCATION_TOKEN: RefCell<Option<AllocationGroupId>> = RefCell::new(None);
+    static CURRENT_ALLOCATION_TOKEN: RefCell<Option<AllocationGroupId>> = const { RefCell::new(None) };
+
+    /// A reference to the currently executing allocation group, kept alongside its ID so that the
+    /// group can be captured without looking it up in the registry.
+    ///
+    /// This is kept separately from the ID, as it has to be dropped when the thread exits, after
+    /// which the ID must still be readable by any allocations that happen.
+    static CURRENT_ALLOCATION_GROUP: RefCell<Option<GroupRef>> = const { RefCell::new(None) };
+
+    /// The stack of allocation groups that are active on this thread, from outermost to innermost.
+    static ACTIVE_STACK: RefCell<ActiveStack> = const { RefCell::new(ActiveStack::new()) };
+}
+
+/// Maximum number of nested allocation groups recorded in the active stack of each thread.
+const MAX_ACTIVE_DEPTH: usize = 32;
+
+type GroupTags = Arc<[(String, String)]>;
+
+/// The registry of allocation groups.
+///
+/// Any code that allocates while holding this lock must do so untracked, as a tracker may itself
+/// look up the tags or parent of a group, which would otherwise deadlock.
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    slots: Vec::new(),
+    free: Vec::new(),
+});
+
+/// The slots that allocation groups are registered into.
+///
+/// The allocation group with index `i` lives in the slot at `i - 1`, as the root allocation group
+/// has no slot.  Once a group is released, its slot is added to the free list, and reused by the
+/// next group to be registered, with a new generation.  A slot whose generations have run out is
+/// retired instead, never being reused, so that IDs are never repeated.
+struct Registry {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+}
+
+struct Slot {
+    generation: u32,
+    owner: Weak<GroupOwner>,
+    parent: Option<GroupRef>,
+    tags: Option<GroupTags>,
+}
+
+impl Registry {
+    fn lock() -> MutexGuard<'static, Registry> {
+        REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Gets the slot of the given allocation group, if it is still registered.
+    fn slot(&self, id: &AllocationGroupId) -> Option<&Slot> {
+        let slot = self.slots.get(id.index.checked_sub(1)?)?;
+        (slot.generation == id.generation).then_some(slot)
+    }
+}
+
+/// A reference that keeps an allocation group registered.
+type GroupRef = Arc<GroupOwner>;
+
+/// The owner of an allocation group, which releases the group once the last reference to it is
+/// dropped.
+#[derive(Debug)]
+struct GroupOwner {
+    id: AllocationGroupId,
 }
 
-static GROUP_ID: AtomicUsize = AtomicUsize::new(1);
-static HIGHEST_GROUP_ID: AtomicUsize = AtomicUsize::new(1);
+impl Drop for GroupOwner {
+    fn drop(&mut self) {
+        // Notify the tracker before the slot can be reused, so that it never sees the next group
+        // registered into the slot before it's done with this one.
+        let tags = Registry::lock()
+            .slot(&self.id)
+            .and_then(|slot| slot.tags.clone());
+        crate::with_installed_tracker(|tracker| {
+            tracker.released(self.id, tags.as_deref().unwrap_or_default())
+        });
+
+        let (parent, tags) = AllocationRegistry::untracked(|| {
+            let mut registry = Registry::lock();
+            let index = self.id.index;
+            let slot = &mut registry.slots[index - 1];
+            slot.owner = Weak::new();
+            let released = (slot.parent.take(), slot.tags.take());
+            if let Some(generation) = slot.generation.checked_add(1) {
+                slot.generation = generation;
+                registry.free.push(index);
+            }
+            released
+        });
+
+        // Releasing our reference to the parent may release the parent as well, which needs the
+        // registry lock, so we only do so once we've let go of it.
+        drop(tags);
+        drop(parent);
+    }
+}
 
 /// The identifier that uniquely identifiers an allocation group.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct AllocationGroupId(usize);
+///
+/// An identifier consists of an index, which is reused once the allocation group is released, and
+/// a generation, which distinguishes between the allocation groups that have used the same index.
+/// Identifiers are ordered by index, and then by generation.
+///
+/// With the `serde-compat` feature enabled, identifiers can be serialized and deserialized as a
+/// structure with `index` and `generation` fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde-compat", derive(serde::Serialize, serde::Deserialize))]
+pub struct AllocationGroupId {
+    index: usize,
+    generation: u32,
+}
 
 impl AllocationGroupId {
     /// Gets the group ID used for allocations which are not made within a registered allocation group.
     pub const fn root() -> AllocationGroupId {
-        AllocationGroupId(0)
+        AllocationGroupId {
+            index: 0,
+            generation: 0,
+        }
+    }
+
+    /// Gets the index of this group ID.
+    ///
+    /// The root allocation group is always `0`, and registered groups are numbered from `1`.
+    /// Indexes are reused once an allocation group is released, so at any given time, the indexes
+    /// in use stay close to the number of allocation groups that are registered, exceeding it only
+    /// by the indexes retired after running out of generations, which allows them to be used to
+    /// index into dense storage.  Trackers that do so should reset their
+    /// storage for a group when notified that it has been
+    /// [released][crate::AllocationTracker::released].
+    pub const fn as_usize(&self) -> usize {
+        self.index
+    }
+
+    /// Gets the generation of this group ID.
+    ///
+    /// The generation is incremented each time an index is reused, so allocation groups that have
+    /// used the same index can be told apart by their generation.  An index is never reused once
+    /// its generation has reached `u32::MAX`.
+    pub const fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Gets the tags that were provided when this allocation group was registered.
+    ///
+    /// If the group was registered without tags, an empty slice is returned.
+    ///
+    /// The tags are shared with the registry rather than copied, but looking them up still takes a
+    /// lock.  Trackers which look up tags for every allocation will incur some overhead in doing so.
+    pub fn tags(&self) -> Arc<[(String, String)]> {
+        AllocationRegistry::untracked(|| {
+            Registry::lock()
+                .slot(self)
+                .and_then(|slot| slot.tags.clone())
+                .unwrap_or_else(|| Arc::new([]))
+        })
+    }
+
+    /// Gets the parent of this allocation group.
+    ///
+    /// Only groups registered with [`AllocationGroupToken::register_child`], or
+    /// [`AllocationGroupToken::register_child_with_tags`], have a parent.  Groups registered
+    /// without one, the root allocation group, and groups that have been released, return `None`.
+    /// A group keeps its parent registered until the group itself is released.
+    ///
+    /// Looking up the parent takes a lock, but does not allocate, so it is safe to call from within
+    /// a tracker.
+    pub fn parent(&self) -> Option<AllocationGroupId> {
+        Registry::lock()
+            .slot(self)
+            .and_then(|slot| slot.parent.as_ref())
+            .map(|parent| parent.id)
+    }
+
+    /// Gets an iterator over the ancestors of this allocation group, starting with its parent and
+    /// ending with the outermost group that has no parent.
+    ///
+    /// As with [`parent`][AllocationGroupId::parent], iterating over the ancestors does not
+    /// allocate.
+    pub fn ancestors(&self) -> Ancestors {
+        Ancestors {
+            next: self.parent(),
+        }
+    }
+
+    /// Whether or not this allocation group is a descendant of the given allocation group.
+    ///
+    /// A group is not a descendant of itself.
+    pub fn is_descendant_of(&self, ancestor: &AllocationGroupId) -> bool {
+        self.ancestors().any(|id| id == *ancestor)
+    }
+
+    /// Creates a group ID from its index and generation.
+    ///
+    /// This is the inverse of [`as_usize`][AllocationGroupId::as_usize] and
+    /// [`generation`][AllocationGroupId::generation], such as for reading back IDs that were written
+    /// out elsewhere.  Creating an ID does not register an allocation group, so the ID may not refer
+    /// to any group that was ever registered.
+    pub const fn from_parts(index: usize, generation: u32) -> AllocationGroupId {
+        AllocationGroupId { index, generation }
+    }
+
+    /// Creates a group ID from its index, with a generation of zero.
+    ///
+    /// This is the inverse of [`as_usize`][AllocationGroupId::as_usize] for groups whose index has
+    /// not been reused.  Once an index has been reused, the generation is needed as well, so
+    /// [`from_parts`][AllocationGroupId::from_parts] should be used instead.
+    pub const fn from_usize(index: usize) -> AllocationGroupId {
+        AllocationGroupId::from_parts(index, 0)
     }
 }
 
-fn register_group_id() -> Option<AllocationGroupId> {
-    let group_id = GROUP_ID.fetch_add(1, Ordering::Relaxed);
-    let highest_group_id = HIGHEST_GROUP_ID.fetch_max(group_id, Ordering::AcqRel);
+impl fmt::Display for AllocationGroupId {
+    /// Formats the ID as its index, followed by its generation if the index has been reused, as
+    /// in `3` or `3.1`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.generation == 0 {
+            fmt::Display::fmt(&self.index, f)
+        } else {
+            // IDs are formatted from within trackers, so we format onto the stack in order to pad
+            // the ID as a whole without allocating.
+            let mut buf = DisplayBuf {
+                buf: [0; DisplayBuf::CAPACITY],
+                len: 0,
+            };
+            write!(buf, "{}.{}", self.index, self.generation)?;
+            f.pad(buf.as_str())
+        }
+    }
+}
+
+/// A buffer large enough to hold any formatted [`AllocationGroupId`].
+struct DisplayBuf {
+    buf: [u8; DisplayBuf::CAPACITY],
+    len: usize,
+}
+
+impl DisplayBuf {
+    // The longest index, a period, and the longest generation.
+    const CAPACITY: usize = 20 + 1 + 10;
 
-    if group_id >= highest_group_id {
-        Some(AllocationGroupId(group_id))
-    } else {
-        None
+    fn as_str(&self) -> &str {
+        // Only ever written to with whole `str`s.
+        std::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
     }
 }
 
+impl fmt::Write for DisplayBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// An iterator over the ancestors of an allocation group.
+///
+/// Created by [`AllocationGroupId::ancestors`].
+#[derive(Debug)]
+pub struct Ancestors {
+    next: Option<AllocationGroupId>,
+}
+
+impl Iterator for Ancestors {
+    type Item = AllocationGroupId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        self.next = current.parent();
+        Some(current)
+    }
+}
+
+/// A fixed-capacity stack of the allocation groups active on a thread.
+///
+/// The stack is updated whenever an allocation group is entered or exited, so it must never
+/// allocate.  Groups nested deeper than [`MAX_ACTIVE_DEPTH`] are counted, but not recorded.
+struct ActiveStack {
+    ids: [AllocationGroupId; MAX_ACTIVE_DEPTH],
+    len: usize,
+}
+
+impl ActiveStack {
+    const fn new() -> Self {
+        const ROOT: AllocationGroupId = AllocationGroupId::root();
+        Self {
+            ids: [ROOT; MAX_ACTIVE_DEPTH],
+            len: 0,
+        }
+    }
+
+    /// Pushes a group onto the stack, returning the depth of the stack before it was pushed.
+    fn push(&mut self, id: AllocationGroupId) -> usize {
+        let depth = self.len;
+        if let Some(slot) = self.ids.get_mut(depth) {
+            *slot = id;
+        }
+        self.len += 1;
+        depth
+    }
+
+    fn truncate(&mut self, depth: usize) {
+        self.len = self.len.min(depth);
+    }
+
+    fn as_slice(&self) -> &[AllocationGroupId] {
+        &self.ids[..self.len.min(MAX_ACTIVE_DEPTH)]
+    }
+}
+
+impl AllocationRegistry {
+    /// Runs the given closure with the stack of allocation groups active on the current thread.
+    ///
+    /// The stack is ordered from the outermost allocation group to the innermost one, which is the
+    /// group that allocations are attributed to, and is empty if no allocation group is active.
+    /// When allocation groups are nested more than 32 deep, only the outermost 32 are included.
+    ///
+    /// This does not allocate, so it can be called from within a tracker, where it gives the groups
+    /// that were active when the allocation being tracked was made.  This allows trackers to credit
+    /// an allocation to every enclosing group, as [`StatsTracker::with_inclusive_stats`] does,
+    /// rather than only the innermost one.
+    ///
+    /// [`StatsTracker::with_inclusive_stats`]: crate::StatsTracker::with_inclusive_stats
+    pub fn with_active_groups<F, R>(f: F) -> R
+    where
+        F: FnOnce(&[AllocationGroupId]) -> R,
+    {
+        let mut f = Some(f);
+        let result = ACTIVE_STACK.try_with(|stack| {
+            let f = f.take().expect("closure already consumed");
+            f(stack.borrow().as_slice())
+        });
+        match result {
+            Ok(result) => result,
+            // The thread-local storage has been torn down, so no allocation group can be active.
+            Err(_) => (f.take().expect("closure already consumed"))(&[]),
+        }
+    }
+}
+
+fn collect_tags<I, K, V>(tags: I) -> GroupTags
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    tags.into_iter()
+        .map(|(k, v)| (k.into(), v.into()))
+        .collect()
+}
+
+fn register_group(
+    parent: Option<&AllocationGroupId>,
+    tags: Option<GroupTags>,
+) -> Option<AllocationGroupToken> {
+    let tags = tags.filter(|tags| !tags.is_empty());
+
+    // We allocate while holding the registry lock, both for the owner of the group and possibly
+    // for growing the registry, so we can't let our own allocations be tracked.
+    let owner = AllocationRegistry::untracked(|| {
+        let mut registry = Registry::lock();
+
+        let index = match registry.free.pop() {
+            Some(index) => index,
+            None => {
+                let index = registry.slots.len().checked_add(1)?;
+                registry.slots.push(Slot {
+                    generation: 0,
+                    owner: Weak::new(),
+                    parent: None,
+                    tags: None,
+                });
+                index
+            }
+        };
+
+        let parent = parent
+            .and_then(|parent| registry.slot(parent))
+            .and_then(|slot| slot.owner.upgrade());
+
+        let slot = &mut registry.slots[index - 1];
+        let owner = Arc::new(GroupOwner {
+            id: AllocationGroupId::from_parts(index, slot.generation),
+        });
+        slot.owner = Arc::downgrade(&owner);
+        slot.parent = parent;
+        slot.tags = tags.clone();
+
+        Some(owner)
+    })?;
+
+    crate::with_installed_tracker(|tracker| {
+        tracker.registered(owner.id, tags.as_deref().unwrap_or_default())
+    });
+
+    Some(AllocationGroupToken(owner))
+}
+
 /// A token that uniquely identifies an allocation group.
 ///
 /// Allocation groups are the core grouping mechanism of `tracking-allocator` and drive much of its
@@ -61,32 +437,93 @@ fn register_group_id() -> Option<AllocationGroupId> {
 /// [`AllocationGuard`] also tracks if another allocation group was active prior to entering, and
 /// ensures it is set back as the active allocation group when the guard is dropped.  This allows
 /// allocation groups to be nested within each other.
-pub struct AllocationGroupToken(AllocationGroupId);
+///
+/// Alternatively, [`in_scope`][AllocationGroupToken::in_scope] runs a closure within the
+/// allocation group while only borrowing the token, and [`AllocationGroupHandle`] allows a single
+/// allocation group to be entered from many threads at once.
+///
+/// ## Releasing allocation groups
+///
+/// An allocation group stays registered for as long as its token, any handles to it, any guards
+/// for it, or any of its child groups are alive.  Once the last of them is dropped, the allocation
+/// group is released: the global tracker, if any, is notified via
+/// [`AllocationTracker::released`][crate::AllocationTracker::released], its tags are dropped, and
+/// its index may be reused by a group registered afterwards, with a new generation.
+pub struct AllocationGroupToken(GroupRef);
 
 impl AllocationGroupToken {
     /// Registers an allocation group token.
     ///
-    /// Allocation group IDs are assigned atomically and monotonically, and cannot exceed the
-    /// maximum value of the target's pointer size i.e. the upper bound for group IDs on 32-bit
-    /// platforms is around 4 billion.
+    /// Allocation group IDs reuse the index of a released allocation group where possible, and
+    /// otherwise take the next unused index, so indexes stay close to the number of allocation
+    /// groups registered at once.  As allocation group IDs are never repeated, a reused index is
+    /// given a new generation, and an index whose generation has reached `u32::MAX` is retired
+    /// rather than reused.
     ///
-    /// If this call would cause a group ID to be generated that had already been acquired within
-    /// this process, `None` will be returned.  Once `None` has been returned, all subsequent calls
-    /// to `register` will return `None`.
+    /// If the number of allocation groups registered at once would exceed the maximum value of the
+    /// target's pointer size, `None` will be returned.
     ///
     /// Otherwise, `Some(token)` is returned.
     pub fn register() -> Option<AllocationGroupToken> {
-        register_group_id().map(AllocationGroupToken)
+        register_group(None, None)
+    }
+
+    /// Registers an allocation group token with the given tags.
+    ///
+    /// Tags are arbitrary key/value pairs that describe the allocation group, such as the name of
+    /// the subsystem it belongs to, and can be retrieved with [`AllocationGroupId::tags`].
+    ///
+    /// Otherwise, this behaves identically to [`register`][AllocationGroupToken::register].
+    pub fn register_with_tags<I, K, V>(tags: I) -> Option<AllocationGroupToken>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        register_group(None, Some(collect_tags(tags)))
+    }
+
+    /// Registers an allocation group token as a child of the given allocation group.
+    ///
+    /// Nesting allocation groups this way, such as a group per request handler within a group per
+    /// service, records the relationship between them, which can be queried with
+    /// [`AllocationGroupId::parent`] and [`AllocationGroupId::ancestors`], and used to roll up
+    /// statistics, as with [`StatsTracker::snapshot_rolled_up`][crate::StatsTracker::snapshot_rolled_up].
+    /// The parent is only recorded, and is not entered when the child is: allocations are still
+    /// attributed to the innermost active group alone.
+    ///
+    /// If the parent is the root allocation group, the group is registered without a parent.
+    ///
+    /// Otherwise, this behaves identically to [`register`][AllocationGroupToken::register].
+    pub fn register_child(parent: &AllocationGroupId) -> Option<AllocationGroupToken> {
+        register_group(Some(parent), None)
+    }
+
+    /// Registers an allocation group token as a child of the given allocation group, with the
+    /// given tags.
+    ///
+    /// This combines [`register_child`][AllocationGroupToken::register_child] and
+    /// [`register_with_tags`][AllocationGroupToken::register_with_tags].
+    pub fn register_child_with_tags<I, K, V>(
+        parent: &AllocationGroupId,
+        tags: I,
+    ) -> Option<AllocationGroupToken>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        register_group(Some(parent), Some(collect_tags(tags)))
     }
 
     /// The ID associated with this allocation group.
     pub fn id(&self) -> AllocationGroupId {
-        self.0.clone()
+        self.0.id
     }
 
     #[cfg(feature = "tracing-compat")]
     pub(crate) fn into_unsafe(self) -> UnsafeAllocationGroupToken {
-        UnsafeAllocationGroupToken::new(self.0)
+        UnsafeAllocationGroupToken::new(self)
     }
 
     /// Marks the associated allocation group as the active allocation group on this thread.
@@ -96,6 +533,82 @@ impl AllocationGroupToken {
     pub fn enter(self) -> AllocationGuard {
         AllocationGuard::enter(self)
     }
+
+    /// Runs the given closure with the associated allocation group as the active allocation group
+    /// on this thread.
+    ///
+    /// Unlike [`enter`][AllocationGroupToken::enter], the token is only borrowed, so it can be used
+    /// again afterwards.  The previously active allocation group, if any, is restored once the
+    /// closure returns, or if it panics.
+    pub fn in_scope<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let _guard = AllocationGuard::enter(AllocationGroupToken(Arc::clone(&self.0)));
+        f()
+    }
+
+    /// Converts this token into a handle that can be cloned and shared between threads.
+    pub fn into_handle(self) -> AllocationGroupHandle {
+        AllocationGroupHandle(self.0)
+    }
+}
+
+/// A shareable handle to an allocation group.
+///
+/// While [`AllocationGroupToken`] must be consumed to be entered, and recovered from the resulting
+/// [`AllocationGuard`] to be entered again, a handle can be entered any number of times, from any
+/// number of threads at once, by cloning it.  This suits long-lived allocation groups, such as one
+/// per subsystem, which are entered from many places.
+///
+/// Handles are created with [`AllocationGroupToken::into_handle`].
+#[derive(Clone, Debug)]
+pub struct AllocationGroupHandle(GroupRef);
+
+impl AllocationGroupHandle {
+    /// Gets a handle to the allocation group that is currently active on this thread, if any.
+    ///
+    /// This allows the active allocation group to be captured, and entered again elsewhere, such as
+    /// on another thread.
+    pub fn current() -> Option<AllocationGroupHandle> {
+        CURRENT_ALLOCATION_GROUP
+            .try_with(|current| current.borrow().clone())
+            .ok()
+            .flatten()
+            .map(AllocationGroupHandle)
+    }
+
+    /// The ID associated with this allocation group.
+    pub fn id(&self) -> AllocationGroupId {
+        self.0.id
+    }
+
+    /// Marks the associated allocation group as the active allocation group on this thread.
+    ///
+    /// If another allocation group is currently active, it is replaced, and restored either when
+    /// the returned allocation guard is dropped, or when [`AllocationGuard::exit`] is called.
+    pub fn enter(&self) -> AllocationGuard {
+        AllocationGuard::enter(AllocationGroupToken(Arc::clone(&self.0)))
+    }
+
+    /// Runs the given closure with the associated allocation group as the active allocation group
+    /// on this thread.
+    ///
+    /// The previously active allocation group, if any, is restored once the closure returns, or if
+    /// it panics.
+    pub fn in_scope<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let _guard = self.enter();
+        f()
+    }
+}
+
+impl From<AllocationGroupToken> for AllocationGroupHandle {
+    fn from(token: AllocationGroupToken) -> Self {
+        token.into_handle()
+    }
 }
 
 #[cfg(feature = "tracing-compat")]
@@ -128,21 +641,29 @@ enum GuardState {
 
     // Guard is active.  We're the active allocation group, so we hold on to the previous
     // allocation group ID, if there was one, so we can switch back to it when we transition to
-    // being idle.
-    Active(Option<AllocationGroupId>),
+    // being idle, along with a reference to the previous allocation group, and the depth of the
+    // active stack before we were pushed onto it.
+    Active(Option<AllocationGroupId>, Option<GroupRef>, usize),
 }
 
 impl GuardState {
-    fn transition_to_active(&mut self) {
+    fn transition_to_active(&mut self, group: &GroupRef) {
         let new_state = match self {
             Self::Idle(id) => {
                 // Set the current allocation token to the new token, keeping the previous.
-                let previous =
-                    CURRENT_ALLOCATION_TOKEN.with(|current| current.replace(Some(id.clone())));
-                Self::Active(previous)
+                let previous = CURRENT_ALLOCATION_TOKEN.with(|current| current.replace(Some(*id)));
+                // The reference can't be set once the thread has started exiting, in which case
+                // the group just can't be captured with `AllocationGroupHandle::current`.
+                let previous_group = CURRENT_ALLOCATION_GROUP
+                    .try_with(|current| current.replace(Some(Arc::clone(group))))
+                    .ok()
+                    .flatten();
+                let depth = ACTIVE_STACK.with(|stack| stack.borrow_mut().push(*id));
+                crate::with_global_tracker(|tracker| tracker.entered(*id));
+                Self::Active(previous, previous_group, depth)
             }
-            Self::Active(ref previous) => {
-                let current = CURRENT_ALLOCATION_TOKEN.with(|current| current.borrow().clone());
+            Self::Active(ref previous, _, _) => {
+                let current = CURRENT_ALLOCATION_TOKEN.with(|current| *current.borrow());
                 panic!(
                     "tid {:?}: transitioning active->active is invalid; current={:?} previous={:?}",
                     std::thread::current().id(),
@@ -167,13 +688,22 @@ impl GuardState {
     fn try_transition_to_idle(&mut self) -> Option<AllocationGroupId> {
         let (id, new_state) = match self {
             Self::Idle(_) => return None,
-            Self::Active(previous) => {
-                // Reset the current allocation token to the previous one:
+            Self::Active(previous, previous_group, depth) => {
+                // Reset the current allocation token to the previous one, and pop ourselves, along
+                // with anything left above us, off of the active stack:
                 let current = CURRENT_ALLOCATION_TOKEN.with(|current| {
                     let old = mem::replace(&mut *current.borrow_mut(), previous.take());
                     old.expect("transitioned to idle state with empty CURRENT_ALLOCATION_TOKEN")
                 });
-                (Some(current.clone()), Self::Idle(current))
+                // Our own reference is only dropped once the thread-local is no longer borrowed,
+                // though the guard still holds one, so it can't release the group regardless.
+                let _ours = CURRENT_ALLOCATION_GROUP
+                    .try_with(|current| current.replace(previous_group.take()))
+                    .ok()
+                    .flatten();
+                ACTIVE_STACK.with(|stack| stack.borrow_mut().truncate(*depth));
+                crate::with_global_tracker(|tracker| tracker.exited(current));
+                (Some(current), Self::Idle(current))
             }
         };
         *self = new_state;
@@ -200,6 +730,9 @@ impl GuardState {
 pub struct AllocationGuard {
     state: GuardState,
 
+    /// Keeps the allocation group registered while the guard is alive.
+    group: GroupRef,
+
     /// ```compile_fail
     /// use tracking_allocator::AllocationGuard;
     /// trait AssertSend: Send {}
@@ -211,11 +744,12 @@ pub struct AllocationGuard {
 
 impl AllocationGuard {
     pub(crate) fn enter(token: AllocationGroupToken) -> AllocationGuard {
-        let mut state = GuardState::Idle(token.0);
-        state.transition_to_active();
+        let mut state = GuardState::Idle(token.id());
+        state.transition_to_active(&token.0);
 
         AllocationGuard {
             state,
+            group: token.0,
             _ns: PhantomNotSend::default(),
         }
     }
@@ -224,9 +758,9 @@ impl AllocationGuard {
     /// active allocation group to the previous value.
     pub fn exit(mut self) -> AllocationGroupToken {
         // Reset the current allocation token to the previous one.
-        let current = self.state.transition_to_idle();
+        let _ = self.state.transition_to_idle();
 
-        AllocationGroupToken(current)
+        AllocationGroupToken(Arc::clone(&self.group))
     }
 }
 
@@ -254,14 +788,16 @@ impl Drop for AllocationGuard {
 #[cfg(feature = "tracing-compat")]
 pub(crate) struct UnsafeAllocationGroupToken {
     state: GuardState,
+    group: GroupRef,
 }
 
 #[cfg(feature = "tracing-compat")]
 impl UnsafeAllocationGroupToken {
     /// Creates a new `UnsafeAllocationGroupToken`.
-    pub fn new(id: AllocationGroupId) -> Self {
+    pub fn new(token: AllocationGroupToken) -> Self {
         Self {
-            state: GuardState::Idle(id),
+            state: GuardState::Idle(token.id()),
+            group: token.0,
         }
     }
 
@@ -272,7 +808,7 @@ impl UnsafeAllocationGroupToken {
     ///
     /// Functionally equivalent to [`AllocationGroupToken::enter`].
     pub fn enter(&mut self) {
-        self.state.transition_to_active();
+        self.state.transition_to_active(&self.group);
     }
 
     /// Unmarks this allocation group as the active allocation group on this thread, resetting the
@@ -288,6 +824,6 @@ impl UnsafeAllocationGroupToken {
 #[inline(always)]
 pub(crate) fn get_active_allocation_group_id() -> AllocationGroupId {
     CURRENT_ALLOCATION_TOKEN
-        .with(|current| current.borrow().clone())
+        .with(|current| *current.borrow())
         .unwrap_or_else(AllocationGroupId::root)
 }
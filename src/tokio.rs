@@ -0,0 +1,103 @@
+//! Allocation group attribution for [`tokio`] tasks.
+//!
+//! Tokio runs tasks on whichever worker thread is free, so the allocation group active on a thread
+//! says nothing about which task is running on it.  The functions in this module wrap
+//! [`tokio::spawn`] so that every task gets its own allocation group, or inherits the allocation
+//! group of the code that spawned it, which is then entered each time the task is polled, as with
+//! [`AllocationGroupExt::with_allocation_group`][crate::AllocationGroupExt].
+//!
+//! Giving each task its own allocation group, tagged with the name of the task, allows per-task
+//! memory usage to be reported by any tracker that aggregates by allocation group, such as
+//! [`StatsTracker`][crate::StatsTracker], making it possible to find the memory-hungry tasks among
+//! thousands.
+//!
+//! The allocation group of a task is released once the task completes and its future is dropped,
+//! so a process that spawns an unbounded number of tasks only needs as many allocation group IDs as
+//! it has tasks at once.  Trackers are notified when this happens, through
+//! [`AllocationTracker::released`][crate::AllocationTracker::released], so they can fold away the
+//! statistics of completed tasks, as [`StatsTracker`][crate::StatsTracker] does.
+use ::tokio::task::JoinHandle;
+use std::future::Future;
+
+use crate::{
+    future::AllocationGroupExt,
+    token::{AllocationGroupHandle, AllocationGroupToken},
+};
+
+/// Tag key used by [`spawn_named`] for the name of the task.
+pub const TASK_TAG: &str = "task";
+
+/// Spawns a task within a new allocation group of its own.
+///
+/// # Panics
+///
+/// Panics if called from outside of a Tokio runtime, just like [`tokio::spawn`].
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_in_group(AllocationGroupToken::register(), future)
+}
+
+/// Spawns a task within a new allocation group of its own, tagged with the name of the task.
+///
+/// The name is attached as the `task` tag of the allocation group.
+///
+/// # Panics
+///
+/// Panics if called from outside of a Tokio runtime, just like [`tokio::spawn`].
+pub fn spawn_named<N, F>(name: N, future: F) -> JoinHandle<F::Output>
+where
+    N: Into<String>,
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_with_tags([(TASK_TAG.to_string(), name.into())], future)
+}
+
+/// Spawns a task within a new allocation group of its own, registered with the given tags.
+///
+/// # Panics
+///
+/// Panics if called from outside of a Tokio runtime, just like [`tokio::spawn`].
+pub fn spawn_with_tags<I, K, V, F>(tags: I, future: F) -> JoinHandle<F::Output>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_in_group(AllocationGroupToken::register_with_tags(tags), future)
+}
+
+/// Spawns a task within the allocation group active on the current thread.
+///
+/// If no allocation group is active, the task runs without one, just as if it were spawned with
+/// [`tokio::spawn`].
+///
+/// # Panics
+///
+/// Panics if called from outside of a Tokio runtime, just like [`tokio::spawn`].
+pub fn spawn_in_current_group<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_in_group(None, future)
+}
+
+fn spawn_in_group<F>(token: Option<AllocationGroupToken>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match token
+        .map(AllocationGroupHandle::from)
+        .or_else(AllocationGroupHandle::current)
+    {
+        Some(group) => ::tokio::spawn(future.with_allocation_group(group)),
+        None => ::tokio::spawn(future),
+    }
+}
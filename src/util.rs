@@ -1,5 +1,7 @@
 use std::marker::PhantomData;
 
+use crate::token::AllocationGroupId;
+
 // `PhantomNotSend` respectfully copied from tokio-rs/tracing, as it's a damn useful snippet.
 //
 // Copyright (c) 2019 Tokio Contributors
@@ -59,3 +61,38 @@ impl PhantomNotSend {
 ///
 /// Trivially safe, as `PhantomNotSend` doesn't have any API.
 unsafe impl Sync for PhantomNotSend {}
+
+/// Writes `s` as a JSON string literal, including the surrounding quotes.
+pub(crate) fn write_json_string<W: std::fmt::Write>(w: &mut W, s: &str) -> std::fmt::Result {
+    w.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            '\n' => w.write_str("\\n")?,
+            '\r' => w.write_str("\\r")?,
+            '\t' => w.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(w, "\\u{:04x}", c as u32)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+/// Gets a human-readable name for the given allocation group.
+pub(crate) fn group_name(group_id: &AllocationGroupId) -> String {
+    if *group_id == AllocationGroupId::root() {
+        "root allocation group".to_string()
+    } else {
+        format!("allocation group {}", group_id)
+    }
+}
+
+/// Appends `value` to `buf` as an unsigned LEB128 varint.
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
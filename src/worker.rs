@@ -0,0 +1,86 @@
+use std::{
+    io,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::AllocationRegistry;
+
+/// Handle to a background thread spawned by one of the built-in samplers or exporters.
+///
+/// The background thread runs until [`finish`][WorkerHandle::finish] is called, or the handle is
+/// dropped, at which point it does any final work, such as flushing its output, and exits.
+pub struct WorkerHandle {
+    shutdown: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl WorkerHandle {
+    /// Spawns a background thread that calls `f` every `interval`.
+    ///
+    /// `f` is called with `true` one last time when the handle is finished or dropped.  If `f`
+    /// returns an error, the background thread exits early, and the error is returned from
+    /// [`finish`][WorkerHandle::finish].  Allocations made by the background thread are not
+    /// tracked.
+    pub(crate) fn spawn_periodic<F>(name: &str, interval: Duration, mut f: F) -> io::Result<Self>
+    where
+        F: FnMut(bool) -> io::Result<()> + Send + 'static,
+    {
+        Self::spawn(name, move |shutdown| loop {
+            let done = match shutdown.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => false,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+            };
+
+            f(done)?;
+            if done {
+                return Ok(());
+            }
+        })
+    }
+
+    /// Spawns a background thread that runs `f`.
+    ///
+    /// `f` is given a receiver that is disconnected when the handle is finished or dropped, which it
+    /// must use to know when to exit.  Allocations made by the background thread are not tracked.
+    pub(crate) fn spawn<F>(name: &str, f: F) -> io::Result<Self>
+    where
+        F: FnOnce(mpsc::Receiver<()>) -> io::Result<()> + Send + 'static,
+    {
+        let (shutdown, shutdown_rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || AllocationRegistry::untracked(|| f(shutdown_rx)))?;
+
+        Ok(Self {
+            shutdown: Some(shutdown),
+            handle: Some(handle),
+        })
+    }
+
+    /// Stops the background thread, and waits for it to exit.
+    ///
+    /// # Errors
+    ///
+    /// If the background thread encountered an error, it is returned.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        drop(self.shutdown.take());
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("background thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}